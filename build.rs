@@ -0,0 +1,13 @@
+use std::path::Path;
+
+// static/raster.wasm is produced by building draw-together-raster for
+// wasm32-unknown-unknown with the `wasm` feature. When it is missing the
+// client falls back to drawing with Canvas2D.
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(raster_wasm)");
+    println!("cargo::rerun-if-changed=static/raster.wasm");
+
+    if Path::new("static/raster.wasm").exists() {
+        println!("cargo::rustc-cfg=raster_wasm");
+    }
+}
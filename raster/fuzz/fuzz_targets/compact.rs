@@ -0,0 +1,18 @@
+#![no_main]
+
+use draw_together_raster::{COMPACT, CompactSession};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut frame = vec![COMPACT];
+    frame.extend(data);
+
+    let messages = CompactSession::default().decode(&frame).messages;
+    for message in &messages {
+        assert_eq!(message.validate(), Ok(()));
+    }
+
+    // whatever decodes survives encoding again
+    let encoded = CompactSession::default().encode(&messages);
+    assert_eq!(CompactSession::default().decode(&encoded).messages, messages);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use draw_together_raster::{ClientMessage, RESOLUTION_HEIGHT, RESOLUTION_WIDTH};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for message in ClientMessage::decode_batch(data) {
+        assert!(message.height > 0);
+        assert!((message.x as usize) < RESOLUTION_WIDTH);
+        assert!((message.y as usize) < RESOLUTION_HEIGHT);
+
+        assert_eq!(ClientMessage::decode(&message.encode()), Ok(message));
+    }
+});
@@ -0,0 +1,57 @@
+#![no_main]
+
+use draw_together_raster::{Canvas, ClientMessage, RESOLUTION_HEIGHT, RESOLUTION_WIDTH};
+use libfuzzer_sys::fuzz_target;
+
+// the first two bytes pick the canvas size, so messages also land on
+// canvases smaller than the coordinates the protocol allows, whether it is
+// anti-aliased and whether it is transparent like the layers above the first
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+
+    let (width, height) = match (data[0], data[1]) {
+        (0, 0) => (RESOLUTION_WIDTH, RESOLUTION_HEIGHT),
+        (width, height) => (width as usize * 8 + 1, height as usize * 4 + 1),
+    };
+
+    let messages = ClientMessage::decode_batch(&data[2..]);
+    let blank = || match data[1] & 1 {
+        1 => Canvas::transparent(width, height),
+        _ => Canvas::new(width, height),
+    };
+    let mut canvas = blank();
+    canvas.set_antialias(data[0] & 1 == 1);
+
+    for message in &messages {
+        let before = canvas.clone();
+        draw_together_raster::rasterise(&mut canvas, message);
+
+        // every changed pixel has to be inside the message bounds, which the
+        // parallel bands and client dirty rects rely on
+        let (min_x, min_y, max_x, max_y) = message.bounds();
+        for (i, (old, new)) in before
+            .to_rgba()
+            .chunks(4)
+            .zip(canvas.to_rgba().chunks(4))
+            .enumerate()
+        {
+            if old != new {
+                let x = (i % width) as i32;
+                let y = (i / width) as i32;
+
+                assert!(
+                    x >= min_x && x <= max_x && y >= min_y && y <= max_y,
+                    "{message:?} wrote ({x}, {y}) outside of its bounds"
+                );
+            }
+        }
+    }
+
+    let mut parallel = blank();
+    parallel.set_antialias(canvas.antialias());
+    draw_together_raster::rasterise_parallel(&mut parallel, &messages);
+
+    assert!(parallel.to_rgba() == canvas.to_rgba());
+});
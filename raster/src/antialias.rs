@@ -0,0 +1,185 @@
+use crate::{ClientMessage, canvas::Band, stroke::capsule_span};
+use std::ops::RangeInclusive;
+
+/// How much of a pixel is inside a shape, out of 255, given the distance
+/// from its centre to the edge of the shape, negative inside of it.
+#[inline(always)]
+fn coverage(distance: f32) -> u8 {
+    ((0.5 - distance).clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// The pixels of the band within the message bounds.
+fn region(
+    band: &Band,
+    message: &ClientMessage,
+) -> Option<(RangeInclusive<usize>, RangeInclusive<usize>)> {
+    let (min_x, min_y, max_x, max_y) = message.bounds();
+    if max_x < 0 || max_y < 0 || min_x >= band.width as i32 {
+        return None;
+    }
+
+    let columns = min_x.max(0) as usize..=(max_x as usize).min(band.width - 1);
+    let rows = band.rows(min_y.max(0) as usize, max_y as usize);
+
+    Some((columns, rows))
+}
+
+/// Distance from `point` to the segment from `a` to `b`.
+#[inline(always)]
+fn segment_distance(point: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (px, py) = (point.0 - a.0, point.1 - a.1);
+
+    let length_sq = dx * dx + dy * dy;
+    let t = match length_sq > 0.0 {
+        true => ((px * dx + py * dy) / length_sq).clamp(0.0, 1.0),
+        false => 0.0,
+    };
+
+    let (ex, ey) = (px - dx * t, py - dy * t);
+    (ex * ex + ey * ey).sqrt()
+}
+
+/// Distance from `point` to the outline of `polygon`.
+fn outline_distance(point: (f32, f32), polygon: &[(f32, f32)]) -> f32 {
+    (0..polygon.len())
+        .map(|i| segment_distance(point, polygon[i], polygon[(i + 1) % polygon.len()]))
+        .fold(f32::MAX, f32::min)
+}
+
+/// Whether `point` is inside `polygon`, by the even-odd rule.
+fn inside(point: (f32, f32), polygon: &[(f32, f32)]) -> bool {
+    let mut inside = false;
+
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+
+        if (a.1 <= point.1) != (b.1 <= point.1)
+            && point.0 < a.0 + (point.1 - a.1) * (b.0 - a.0) / (b.1 - a.1)
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// A smooth version of the circles, `height` pixels in radius. Hollow ones
+/// keep the same 2 pixel wide ring.
+pub(crate) fn draw_circle(band: &mut Band, message: &ClientMessage, hollow: bool) {
+    let Some((columns, rows)) = region(band, message) else {
+        return;
+    };
+
+    let radius = message.height as f32;
+    let (center_x, center_y) = (message.x as f32, message.y as f32);
+
+    for y in rows {
+        let dy = y as f32 - center_y;
+
+        for x in columns.clone() {
+            let dx = x as f32 - center_x;
+            let distance = (dx * dx + dy * dy).sqrt() - radius;
+
+            let distance = match hollow {
+                true => distance.max(-distance - 2.0),
+                false => distance,
+            };
+
+            band.cover(x, y, &message.color, coverage(distance));
+        }
+    }
+}
+
+/// A smooth version of the polygons. Hollow ones keep the same
+/// 2 pixel wide outline, which lies to the bottom right of the edges.
+pub(crate) fn draw_polygon(
+    band: &mut Band,
+    message: &ClientMessage,
+    polygon: &[(f32, f32)],
+    hollow: bool,
+) {
+    let Some((columns, rows)) = region(band, message) else {
+        return;
+    };
+
+    for y in rows {
+        for x in columns.clone() {
+            let point = (x as f32, y as f32);
+
+            let distance = match hollow {
+                true => outline_distance((point.0 - 0.5, point.1 - 0.5), polygon) - 1.0,
+                false => match inside(point, polygon) {
+                    true => -outline_distance(point, polygon),
+                    false => outline_distance(point, polygon),
+                },
+            };
+
+            band.cover(x, y, &message.color, coverage(distance));
+        }
+    }
+}
+
+/// A smooth version of the polylines drawn by lines and strokes.
+pub(crate) fn draw_path(
+    band: &mut Band,
+    message: &ClientMessage,
+    path: &[(f32, f32)],
+    radius: f32,
+) {
+    let Some((columns, rows)) = region(band, message) else {
+        return;
+    };
+
+    let segments = match path.len() {
+        1 => vec![(path[0], path[0])],
+        _ => path
+            .windows(2)
+            .map(|segment| (segment[0], segment[1]))
+            .collect(),
+    };
+
+    let reach = radius + 0.5;
+    let mut near = Vec::with_capacity(segments.len());
+    let mut spans = Vec::with_capacity(segments.len());
+    for y in rows {
+        let point_y = y as f32;
+
+        // only segments that can reach this row, and the merged columns they do
+        near.clear();
+        spans.clear();
+        for &(a, b) in &segments {
+            if let Some(span) = capsule_span(a, b, reach, point_y) {
+                near.push((a, b));
+                spans.push(span);
+            }
+        }
+
+        spans.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let mut merged: Vec<(f32, f32)> = Vec::with_capacity(spans.len());
+        for &(start, end) in &spans {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + 1.0 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        for (start, end) in merged {
+            let start = (start.ceil().max(0.0) as usize).max(*columns.start());
+            let end = end.floor().min(*columns.end() as f32);
+            if end < start as f32 {
+                continue;
+            }
+
+            for x in start..=end as usize {
+                let point = (x as f32, point_y);
+                let distance = near
+                    .iter()
+                    .map(|(a, b)| segment_distance(point, *a, *b))
+                    .fold(f32::MAX, f32::min);
+
+                band.cover(x, y, &message.color, coverage(distance - radius));
+            }
+        }
+    }
+}
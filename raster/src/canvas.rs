@@ -0,0 +1,430 @@
+use crate::{Blend, Style};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::ops::RangeInclusive;
+
+const WHITE: [u8; 3] = [0xFF; 3];
+
+/// An RGB pixel buffer stored row by row, 3 bytes per pixel, with an
+/// optional alpha channel for layers that can be seen through.
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+    alpha: Option<Vec<u8>>,
+    antialias: bool,
+}
+
+impl Canvas {
+    /// Creates a white canvas.
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && height > 0, "canvas must not be empty");
+
+        Self {
+            width,
+            height,
+            data: vec![0xFF; width * height * 3],
+            alpha: None,
+            antialias: false,
+        }
+    }
+
+    /// Creates a fully transparent canvas. Transparent pixels are white, so
+    /// they read like an empty opaque canvas.
+    pub fn transparent(width: usize, height: usize) -> Self {
+        Self {
+            alpha: Some(vec![0; width * height]),
+            ..Self::new(width, height)
+        }
+    }
+
+    /// Wraps an existing RGB buffer, returning `None` if its length does not
+    /// match the given size.
+    pub fn from_raw(width: usize, height: usize, data: Vec<u8>) -> Option<Self> {
+        if width == 0 || height == 0 || data.len() != width * height * 3 {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            data,
+            alpha: None,
+            antialias: false,
+        })
+    }
+
+    /// Wraps an existing RGBA buffer as a transparent canvas, returning
+    /// `None` if its length does not match the given size.
+    pub fn from_rgba(width: usize, height: usize, rgba: &[u8]) -> Option<Self> {
+        if width == 0 || height == 0 || rgba.len() != width * height * 4 {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(width * height * 3);
+        let mut alpha = Vec::with_capacity(width * height);
+        for pixel in rgba.chunks(4) {
+            match pixel[3] {
+                0 => data.extend(WHITE),
+                _ => data.extend(&pixel[..3]),
+            }
+            alpha.push(pixel[3]);
+        }
+
+        Some(Self {
+            width,
+            height,
+            data,
+            alpha: Some(alpha),
+            antialias: false,
+        })
+    }
+
+    /// Whether circles, triangles, hexagons, lines and strokes are painted
+    /// with smooth edges, blending the pixels they partially cover.
+    #[inline]
+    pub fn antialias(&self) -> bool {
+        self.antialias
+    }
+
+    #[inline]
+    pub fn set_antialias(&mut self, antialias: bool) {
+        self.antialias = antialias;
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The colour at `(x, y)`, or `None` outside of the canvas.
+    #[inline]
+    pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let index = (y * self.width + x) * 3;
+        Some([self.data[index], self.data[index + 1], self.data[index + 2]])
+    }
+
+    /// How opaque `(x, y)` is, always 255 on canvases without an alpha
+    /// channel, or `None` outside of the canvas.
+    #[inline]
+    pub fn alpha(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        match &self.alpha {
+            Some(alpha) => Some(alpha[y * self.width + x]),
+            None => Some(0xFF),
+        }
+    }
+
+    /// Whether the canvas has an alpha channel, see [`Canvas::transparent`].
+    #[inline]
+    pub fn is_transparent(&self) -> bool {
+        self.alpha.is_some()
+    }
+
+    /// The pixels as RGBA, row by row.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.width * self.height * 4);
+        for (i, pixel) in self.data.chunks(3).enumerate() {
+            rgba.extend(pixel);
+            rgba.push(self.alpha.as_ref().map_or(0xFF, |alpha| alpha[i]));
+        }
+
+        rgba
+    }
+
+    /// A copy of the `width` by `height` rectangle at `(x, y)`, or `None` if
+    /// it is not within the canvas.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Option<Self> {
+        if x.checked_add(width)? > self.width || y.checked_add(height)? > self.height {
+            return None;
+        }
+
+        let rows = y..y + height;
+        let data = rows
+            .clone()
+            .flat_map(|row| {
+                let start = (row * self.width + x) * 3;
+                &self.data[start..start + width * 3]
+            })
+            .copied()
+            .collect();
+        let alpha = self.alpha.as_ref().map(|alpha| {
+            rows.flat_map(|row| {
+                let start = row * self.width + x;
+                &alpha[start..start + width]
+            })
+            .copied()
+            .collect()
+        });
+
+        Some(Self {
+            width,
+            height,
+            data,
+            alpha,
+            antialias: self.antialias,
+        })
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The alpha channel, one byte per pixel, see [`Canvas::transparent`].
+    #[inline]
+    pub fn alpha_bytes(&self) -> Option<&[u8]> {
+        self.alpha.as_deref()
+    }
+
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub(crate) fn band(&mut self) -> Band<'_> {
+        Band::new(
+            &mut self.data,
+            self.alpha.as_deref_mut(),
+            self.width,
+            self.height,
+            0,
+            self.antialias,
+        )
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(crate) fn bands(&mut self, rows: usize) -> impl ParallelIterator<Item = Band<'_>> {
+        let (width, height, antialias) = (self.width, self.height, self.antialias);
+
+        let alpha: Vec<Option<&mut [u8]>> = match &mut self.alpha {
+            Some(alpha) => alpha.chunks_mut(rows * width).map(Some).collect(),
+            None => (0..height.div_ceil(rows)).map(|_| None).collect(),
+        };
+
+        self.data
+            .par_chunks_mut(rows * width * 3)
+            .zip(alpha)
+            .enumerate()
+            .map(move |(i, (chunk, alpha))| {
+                Band::new(chunk, alpha, width, height, i * rows, antialias)
+            })
+    }
+}
+
+/// A horizontal strip of a canvas covering the rows `start_y..end_y`.
+///
+/// Every pixel belongs to exactly one band, so bands can be painted on
+/// separate threads while each one still applies messages in order.
+pub(crate) struct Band<'a> {
+    data: &'a mut [u8],
+    alpha: Option<&'a mut [u8]>,
+    blending: Option<Blending>,
+    pub antialias: bool,
+    pub width: usize,
+    pub height: usize,
+    pub start_y: usize,
+    pub end_y: usize,
+}
+
+/// The style of the message being painted, if it does not simply replace
+/// pixels, and which pixels within its bounds it has painted so far.
+/// Shapes may paint a pixel more than once, but it must only be blended once.
+struct Blending {
+    style: Style,
+    min_x: usize,
+    min_y: usize,
+    width: usize,
+    painted: Vec<bool>,
+}
+
+impl<'a> Band<'a> {
+    fn new(
+        data: &'a mut [u8],
+        alpha: Option<&'a mut [u8]>,
+        width: usize,
+        height: usize,
+        start_y: usize,
+        antialias: bool,
+    ) -> Self {
+        let end_y = start_y + data.len() / (width * 3);
+
+        Self {
+            data,
+            alpha,
+            blending: None,
+            antialias,
+            width,
+            height,
+            start_y,
+            end_y,
+        }
+    }
+
+    #[inline(always)]
+    pub fn contains_row(&self, y: usize) -> bool {
+        y >= self.start_y && y < self.end_y
+    }
+
+    #[inline(always)]
+    pub fn rows(&self, start_y: usize, end_y: usize) -> RangeInclusive<usize> {
+        start_y.max(self.start_y)..=end_y.min(self.end_y.saturating_sub(1))
+    }
+
+    /// Makes [`Band::set`] blend with `style` until the next call, for a
+    /// message that stays within the inclusive `bounds`.
+    pub fn set_style(&mut self, style: Style, bounds: (i32, i32, i32, i32)) {
+        self.blending = None;
+        if style.replaces() {
+            return;
+        }
+
+        let (min_x, min_y, max_x, max_y) = bounds;
+        let min_x = min_x.clamp(0, self.width as i32) as usize;
+        let max_x = (max_x + 1).clamp(0, self.width as i32) as usize;
+        let rows = self.rows(min_y.max(0) as usize, max_y.max(0) as usize);
+
+        let width = max_x - min_x;
+        let height = (rows.end() + 1).saturating_sub(*rows.start());
+        self.blending = Some(Blending {
+            style,
+            min_x,
+            min_y: *rows.start(),
+            width,
+            painted: vec![false; width * height],
+        });
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, x: usize, y: usize, color: &[u8; 3]) {
+        self.cover(x, y, color, 0xFF);
+    }
+
+    /// Like [`Band::set`] for a pixel that is only `coverage` out of 255
+    /// inside the shape, which scales the opacity it is painted with.
+    #[inline(always)]
+    pub fn cover(&mut self, x: usize, y: usize, color: &[u8; 3], coverage: u8) {
+        if coverage == 0 || !self.claim(x, y) {
+            return;
+        }
+
+        let index = (y - self.start_y) * self.width + x;
+        let pixel = &mut self.data[index * 3..index * 3 + 3];
+        let style = self
+            .blending
+            .as_ref()
+            .map_or(Style::default(), |blending| blending.style);
+
+        match &mut self.alpha {
+            Some(alpha) => blend_transparent(pixel, &mut alpha[index], color, &style, coverage),
+            None if coverage == 0xFF && style.replaces() => pixel.copy_from_slice(color),
+            None => blend(pixel, color, &style, coverage),
+        }
+    }
+
+    /// Erases a pixel to white, or on a transparent canvas makes it see
+    /// through by the opacity of the message.
+    #[inline(always)]
+    pub fn clear(&mut self, x: usize, y: usize) {
+        let index = (y - self.start_y) * self.width + x;
+        match &self.alpha {
+            None => return self.set(x, y, &WHITE),
+            Some(alpha) if alpha[index] == 0 => return,
+            Some(_) => {}
+        }
+        if !self.claim(x, y) {
+            return;
+        }
+
+        let opacity = self
+            .blending
+            .as_ref()
+            .map_or(0xFF, |blending| blending.style.opacity);
+        let alpha = &mut self.alpha.as_mut().unwrap()[index];
+
+        *alpha = ((*alpha as u32 * (255 - opacity as u32) + 127) / 255) as u8;
+        if *alpha == 0 {
+            self.data[index * 3..index * 3 + 3].copy_from_slice(&WHITE);
+        }
+    }
+
+    /// Whether `(x, y)` has not been painted by the current message yet, and
+    /// marks it as painted.
+    #[inline(always)]
+    fn claim(&mut self, x: usize, y: usize) -> bool {
+        let Some(blending) = &mut self.blending else {
+            return true;
+        };
+
+        // pixels outside of the bounds are a bug in the shape, but should
+        // still be painted rather than panic
+        let painted = x
+            .checked_sub(blending.min_x)
+            .filter(|x| *x < blending.width)
+            .zip(y.checked_sub(blending.min_y))
+            .and_then(|(x, y)| blending.painted.get_mut(y * blending.width + x));
+        match painted {
+            Some(true) => false,
+            Some(painted) => {
+                *painted = true;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+fn blend(pixel: &mut [u8], color: &[u8; 3], style: &Style, coverage: u8) {
+    let opacity = (style.opacity as u32 * coverage as u32 + 127) / 255;
+
+    for (pixel, color) in pixel.iter_mut().zip(color) {
+        let under = *pixel as u32;
+        let over = match style.blend {
+            Blend::Normal => *color as u32,
+            Blend::Multiply => (*color as u32 * under + 127) / 255,
+        };
+
+        *pixel = ((over * opacity + under * (255 - opacity) + 127) / 255) as u8;
+    }
+}
+
+/// Like [`blend`] for a pixel of a transparent canvas, which becomes as
+/// opaque as the paint over it.
+fn blend_transparent(
+    pixel: &mut [u8],
+    alpha: &mut u8,
+    color: &[u8; 3],
+    style: &Style,
+    coverage: u8,
+) {
+    let opacity = (style.opacity as u32 * coverage as u32 + 127) / 255;
+    let under_alpha = *alpha as u32 * (255 - opacity);
+    let out_alpha = opacity * 255 + under_alpha;
+    if out_alpha == 0 {
+        return;
+    }
+
+    for (pixel, color) in pixel.iter_mut().zip(color) {
+        let under = *pixel as u32;
+        let over = match style.blend {
+            Blend::Normal => *color as u32,
+            Blend::Multiply => (*color as u32 * under + 127) / 255,
+        };
+
+        *pixel = ((over * opacity * 255 + under * under_alpha + out_alpha / 2) / out_alpha) as u8;
+    }
+    *alpha = ((out_alpha + 127) / 255) as u8;
+}
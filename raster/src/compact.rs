@@ -0,0 +1,356 @@
+use crate::{
+    Action, Batch, ClientMessage, DecodeError, MAX_STROKE_POINTS, RESOLUTION_HEIGHT,
+    RESOLUTION_WIDTH, Style,
+};
+
+/// First byte of a compact frame. Its high nibble is not a legacy action, and
+/// [`ClientMessage::decode_batch_checked`] rejects a frame starting with it
+/// as a whole. Older decoders would skip it as one unknown message and
+/// misread the rest, so it is only sent to clients that ask for it.
+pub const COMPACT: u8 = 0xE0;
+/// Most colours a [`CompactSession`] palette holds.
+pub const PALETTE_SIZE: usize = 64;
+
+/// The record is a brush stroke, see [`Action::Stroke`].
+const STROKE: u8 = 9;
+/// The record is a whole message in the legacy or extended format.
+const EMBEDDED: u8 = 0xF;
+/// A stroke point that does not fit in one byte, its deltas follow.
+const WIDE_POINT: u8 = 0x88;
+const SAME_HEIGHT: u8 = 0x10;
+const SAME_COLOR: u8 = 0x20;
+const PALETTE_COLOR: u8 = 0x40;
+
+// compact format, a frame that starts with 0xE0 followed by records:
+// (4b) action  | header, 0-8 for the legacy actions, 9 for a stroke, 0xF for
+//                an embedded message
+// (1b) same height
+// (1b) same colour
+// (1b) palette colour
+// (1b) reserved, must be 0
+//
+// for an embedded message, the flags are 0 and the message follows as is,
+// otherwise:
+// (varint) dx, zigzag encoded, from the previous message
+// (varint) dy, zigzag encoded, from the previous message
+// (8b) height, unless it is the same
+// (8b) palette index, or (24b) colour, unless it is the same
+//
+// a stroke record goes on with its points:
+// (varint) number of points
+// (8b) each point, dx in the high and dy in the low nibble as 4 bit two's
+//      complement, or 0x88 followed by (8b) dx and (8b) dy when they do not fit
+//
+// strokes without a style on the bottom layer are stroke records, other
+// messages that are not 7 byte legacy ones are embedded. The position after a
+// stroke is where it ends, where the next one usually starts
+//
+// both sides keep a palette of the most recently used colours, a colour
+// sent in full is put in front of it and one sent by index moves to the
+// front, so indices stay small while a few colours are in use
+
+/// The state both ends of a connection keep to encode and decode compact
+/// frames: the previous message, which positions, heights and colours are
+/// relative to, or the end of it for a stroke, and the palette. Every frame has to go through the same
+/// session, in order.
+#[derive(Debug, Clone, Default)]
+pub struct CompactSession {
+    x: i32,
+    y: i32,
+    height: u8,
+    color: [u8; 3],
+    palette: Vec<[u8; 3]>,
+}
+
+impl CompactSession {
+    /// Encodes `messages` as one compact frame.
+    pub fn encode<'a>(&mut self, messages: impl IntoIterator<Item = &'a ClientMessage>) -> Vec<u8> {
+        let mut buf = vec![COMPACT];
+
+        for message in messages {
+            let plain = message.style == Style::default() && message.layer == 0;
+            let action = match &message.action {
+                Action::Stroke { .. } if plain => STROKE,
+                _ => {
+                    let encoded = message.encode();
+
+                    // only the 7 byte legacy messages have a shorter form
+                    if encoded.len() != 7 {
+                        buf.push(EMBEDDED);
+                        buf.extend(encoded);
+                        self.advance(message);
+                        continue;
+                    }
+
+                    encoded[0] >> 4
+                }
+            };
+
+            let header = buf.len();
+            buf.push(action);
+            write_varint(&mut buf, zigzag(message.x as i32 - self.x));
+            write_varint(&mut buf, zigzag(message.y as i32 - self.y));
+
+            match message.height == self.height {
+                true => buf[header] |= SAME_HEIGHT,
+                false => buf.push(message.height),
+            }
+
+            if message.color == self.color {
+                buf[header] |= SAME_COLOR;
+            } else if let Some(index) = self.palette.iter().position(|c| *c == message.color) {
+                buf[header] |= PALETTE_COLOR;
+                buf.push(index as u8);
+                self.use_color(index);
+            } else {
+                buf.extend(message.color);
+                self.add_color(message.color);
+            }
+
+            if let Action::Stroke { points } = &message.action {
+                write_varint(&mut buf, points.len() as u32);
+                for &(dx, dy) in points {
+                    match (-8..8).contains(&dx) && (-8..8).contains(&dy) {
+                        true if (dx, dy) != (-8, -8) => {
+                            buf.push(((dx as u8 & 0xF) << 4) | (dy as u8 & 0xF))
+                        }
+                        _ => buf.extend([WIDE_POINT, dx as u8, dy as u8]),
+                    }
+                }
+            }
+
+            self.advance(message);
+        }
+
+        buf
+    }
+
+    /// Decodes a frame, compact if it starts with [`COMPACT`] and like
+    /// [`ClientMessage::decode_batch_checked`] otherwise. A record that
+    /// cannot be read ends the frame, as the ones after it cannot be found.
+    pub fn decode(&mut self, frame: &[u8]) -> Batch {
+        let Some((&COMPACT, mut data)) = frame.split_first() else {
+            return ClientMessage::decode_batch_checked(frame);
+        };
+
+        let mut batch = Batch::default();
+        while !data.is_empty() {
+            match self.decode_record(data) {
+                Ok((message, len)) => {
+                    match message {
+                        Ok(message) => batch.messages.push(message),
+                        Err(err) => batch.errors.push(err),
+                    }
+                    data = &data[len..];
+                }
+                Err(err) => {
+                    batch.errors.push(err);
+                    break;
+                }
+            }
+        }
+
+        batch
+    }
+
+    /// Reads the record at the start of `data`, returning the message or
+    /// why it is invalid, and its length. Fails if the record itself cannot
+    /// be read.
+    fn decode_record(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(Result<ClientMessage, DecodeError>, usize), DecodeError> {
+        let header = data[0];
+        let mut rest = &data[1..];
+
+        if header & 0xF == EMBEDDED {
+            if header & 0xF0 != 0 {
+                return Err(DecodeError::ReservedFlags);
+            }
+
+            let (message, len) = ClientMessage::decode_next(rest).ok_or(DecodeError::Truncated)?;
+            if let Ok(message) = &message {
+                self.advance(message);
+            }
+
+            return Ok((message, 1 + len));
+        }
+        if header & 0x80 != 0 || header & (SAME_COLOR | PALETTE_COLOR) == SAME_COLOR | PALETTE_COLOR
+        {
+            return Err(DecodeError::ReservedFlags);
+        }
+
+        let dx = unzigzag(read_varint(&mut rest)?);
+        let dy = unzigzag(read_varint(&mut rest)?);
+        self.x = self.x.saturating_add(dx);
+        self.y = self.y.saturating_add(dy);
+
+        if header & SAME_HEIGHT == 0 {
+            let (&height, after) = rest.split_first().ok_or(DecodeError::Truncated)?;
+            self.height = height;
+            rest = after;
+        }
+
+        let mut palette_miss = false;
+        if header & PALETTE_COLOR != 0 {
+            let (&index, after) = rest.split_first().ok_or(DecodeError::Truncated)?;
+            match (index as usize) < self.palette.len() {
+                true => {
+                    self.color = self.palette[index as usize];
+                    self.use_color(index as usize);
+                }
+                false => palette_miss = true,
+            }
+            rest = after;
+        } else if header & SAME_COLOR == 0 {
+            let [r, g, b, after @ ..] = rest else {
+                return Err(DecodeError::Truncated);
+            };
+            self.color = [*r, *g, *b];
+            self.add_color(self.color);
+            rest = after;
+        }
+
+        let (x, y) = (self.x, self.y);
+        let points = match header & 0xF {
+            STROKE => {
+                let points = read_points(&mut rest)?;
+                let (dx, dy) = distance(&points);
+                self.x = self.x.saturating_add(dx);
+                self.y = self.y.saturating_add(dy);
+
+                Some(points)
+            }
+            _ => None,
+        };
+
+        let len = data.len() - rest.len();
+        if palette_miss {
+            return Ok((Err(DecodeError::InvalidValue), len));
+        }
+        if !(0..RESOLUTION_WIDTH as i32).contains(&x) || !(0..RESOLUTION_HEIGHT as i32).contains(&y)
+        {
+            return Ok((Err(DecodeError::OutOfBounds), len));
+        }
+        let (x, y) = (x as u16, y as u16);
+
+        if let Some(points) = points {
+            let message = ClientMessage {
+                action: Action::Stroke { points },
+                x,
+                y,
+                height: self.height,
+                color: self.color,
+                style: Style::default(),
+                layer: 0,
+            };
+
+            return Ok((message.validate().map(|()| message), len));
+        }
+        if self.height > 0x7F {
+            return Ok((Err(DecodeError::HeightTooLarge), len));
+        }
+
+        // the legacy format validates the rest
+        let legacy = [
+            ((header & 0xF) << 4) | (self.height >> 3),
+            ((self.height & 0x7) << 5) | (x >> 6) as u8,
+            (((x & 0x3F) << 2) | (y >> 8)) as u8,
+            y as u8,
+            self.color[0],
+            self.color[1],
+            self.color[2],
+        ];
+
+        Ok((ClientMessage::decode(&legacy), len))
+    }
+
+    fn advance(&mut self, message: &ClientMessage) {
+        let (dx, dy) = match &message.action {
+            Action::Stroke { points } => distance(points),
+            _ => (0, 0),
+        };
+        self.x = message.x as i32 + dx;
+        self.y = message.y as i32 + dy;
+        self.height = message.height;
+        self.color = message.color;
+    }
+
+    fn use_color(&mut self, index: usize) {
+        let color = self.palette.remove(index);
+        self.palette.insert(0, color);
+    }
+
+    fn add_color(&mut self, color: [u8; 3]) {
+        self.palette.insert(0, color);
+        self.palette.truncate(PALETTE_SIZE);
+    }
+}
+
+/// How far the end of a stroke is from its start.
+fn distance(points: &[(i8, i8)]) -> (i32, i32) {
+    points
+        .iter()
+        .fold((0, 0), |(x, y), &(dx, dy)| (x + dx as i32, y + dy as i32))
+}
+
+/// Reads the points of a stroke record, failing on more than a stroke may
+/// have.
+fn read_points(data: &mut &[u8]) -> Result<Vec<(i8, i8)>, DecodeError> {
+    let count = read_varint(data)? as usize;
+    if count > MAX_STROKE_POINTS {
+        return Err(DecodeError::TooLarge);
+    }
+
+    let mut points = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (&byte, rest) = data.split_first().ok_or(DecodeError::Truncated)?;
+        *data = rest;
+
+        if byte != WIDE_POINT {
+            // shifted back down with the sign
+            points.push(((byte as i8) >> 4, ((byte << 4) as i8) >> 4));
+            continue;
+        }
+
+        let [dx, dy, rest @ ..] = *data else {
+            return Err(DecodeError::Truncated);
+        };
+        *data = rest;
+        points.push((*dx as i8, *dy as i8));
+    }
+
+    Ok(points)
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u32, DecodeError> {
+    let mut value = 0u32;
+
+    for shift in (0..35).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or(DecodeError::Truncated)?;
+        *data = rest;
+
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(DecodeError::InvalidValue)
+}
@@ -0,0 +1,99 @@
+use crate::{Action, Canvas, ClientMessage, MAX_SPANS, Span};
+
+/// Runs a [`Action::FloodFill`] against the canvas and returns the
+/// [`Action::DrawSpans`] messages that paint its result.
+///
+/// Returns `None` if more than `max_area` pixels would change, and no
+/// messages if the area already has the fill colour.
+pub fn flood_fill(
+    canvas: &Canvas,
+    message: &ClientMessage,
+    max_area: usize,
+) -> Option<Vec<ClientMessage>> {
+    let (width, height) = (canvas.width(), canvas.height());
+    let target = colour(canvas, message.x as usize, message.y as usize)?;
+    if target == (message.color, 0xFF) {
+        return Some(Vec::new());
+    }
+
+    let mut visited = vec![false; width * height];
+    let matches = |visited: &[bool], x: usize, y: usize| {
+        !visited[y * width + x] && colour(canvas, x, y) == Some(target)
+    };
+
+    let mut spans = Vec::new();
+    let mut area = 0;
+    let mut stack = vec![(message.x as usize, message.y as usize)];
+
+    while let Some((x, y)) = stack.pop() {
+        if !matches(&visited, x, y) {
+            continue;
+        }
+
+        let mut start_x = x;
+        while start_x > 0 && matches(&visited, start_x - 1, y) {
+            start_x -= 1;
+        }
+        let mut end_x = x;
+        while end_x + 1 < width && matches(&visited, end_x + 1, y) {
+            end_x += 1;
+        }
+
+        visited[y * width + start_x..=y * width + end_x].fill(true);
+
+        area += end_x - start_x + 1;
+        if area > max_area {
+            return None;
+        }
+
+        spans.push(Span {
+            x: start_x as u16,
+            y: y as u16,
+            length: (end_x - start_x + 1) as u16,
+        });
+
+        // queue the start of every run above and below
+        for neighbour_y in [y.wrapping_sub(1), y + 1] {
+            if neighbour_y >= height {
+                continue;
+            }
+
+            let mut in_run = false;
+            for neighbour_x in start_x..=end_x {
+                let matching = matches(&visited, neighbour_x, neighbour_y);
+                if matching && !in_run {
+                    stack.push((neighbour_x, neighbour_y));
+                }
+
+                in_run = matching;
+            }
+        }
+    }
+
+    Some(
+        spans
+            .chunks(MAX_SPANS)
+            .map(|spans| ClientMessage {
+                action: Action::DrawSpans {
+                    spans: spans.to_vec(),
+                },
+                x: message.x,
+                y: message.y,
+                height: message.height,
+                color: message.color,
+                style: message.style,
+                layer: message.layer,
+            })
+            .collect(),
+    )
+}
+
+/// The colour and opacity a fill compares, so that on transparent layers
+/// empty pixels are not taken for opaque ones of the same colour. Fully
+/// transparent pixels are all alike, whatever colour they keep.
+fn colour(canvas: &Canvas, x: usize, y: usize) -> Option<([u8; 3], u8)> {
+    match canvas.alpha(x, y)? {
+        0 => Some(([0; 3], 0)),
+        alpha => Some((canvas.pixel(x, y)?, alpha)),
+    }
+}
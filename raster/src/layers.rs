@@ -0,0 +1,224 @@
+use crate::{Canvas, ClientMessage, MAX_LAYERS, Style, rasterise};
+
+/// One canvas of a [`Layers`] stack.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    canvas: Canvas,
+    /// Hidden layers are left out of [`Layers::composite`].
+    pub visible: bool,
+    /// Locked layers are not painted on.
+    pub locked: bool,
+}
+
+impl Layer {
+    #[inline]
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+}
+
+/// Canvases of the same size painted on separately and composited from the
+/// bottom up. The bottom layer is opaque, the ones above it transparent.
+#[derive(Debug, Clone)]
+pub struct Layers {
+    layers: Vec<Layer>,
+}
+
+impl Layers {
+    /// Creates `count` empty, visible and unlocked layers.
+    pub fn new(width: usize, height: usize, count: usize) -> Self {
+        assert!(
+            (1..=MAX_LAYERS as usize).contains(&count),
+            "invalid layer count"
+        );
+
+        let layers = (0..count)
+            .map(|i| Layer {
+                canvas: match i {
+                    0 => Canvas::new(width, height),
+                    _ => Canvas::transparent(width, height),
+                },
+                visible: true,
+                locked: false,
+            })
+            .collect();
+
+        Self { layers }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.layers[0].canvas.width()
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.layers[0].canvas.height()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    #[inline]
+    pub fn layer(&self, layer: u8) -> Option<&Layer> {
+        self.layers.get(layer as usize)
+    }
+
+    #[inline]
+    pub fn layer_mut(&mut self, layer: u8) -> Option<&mut Layer> {
+        self.layers.get_mut(layer as usize)
+    }
+
+    /// Replaces the canvas of `layer`, returning `false` if there is no such
+    /// layer or the canvas does not match it in size and transparency.
+    pub fn load(&mut self, layer: u8, mut canvas: Canvas) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let Some(layer) = self.layers.get_mut(layer as usize) else {
+            return false;
+        };
+
+        if canvas.width() != width
+            || canvas.height() != height
+            || canvas.is_transparent() != layer.canvas.is_transparent()
+        {
+            return false;
+        }
+
+        canvas.set_antialias(layer.canvas.antialias());
+        layer.canvas = canvas;
+
+        true
+    }
+
+    /// See [`Canvas::antialias`].
+    #[inline]
+    pub fn antialias(&self) -> bool {
+        self.layers[0].canvas.antialias()
+    }
+
+    pub fn set_antialias(&mut self, antialias: bool) {
+        for layer in &mut self.layers {
+            layer.canvas.set_antialias(antialias);
+        }
+    }
+
+    /// Whether painting `message` a second time leaves the pixels as they
+    /// are, so a client may paint its own messages before they come back
+    /// from the server. Translucent, multiplied and anti-aliased paint
+    /// blends with itself.
+    pub fn repaints_identically(&self, message: &ClientMessage) -> bool {
+        message.style == Style::default() && !self.antialias()
+    }
+
+    /// Paints a single message onto its layer, unless the layer is locked or
+    /// does not exist.
+    pub fn rasterise(&mut self, message: &ClientMessage) {
+        match self.layers.get_mut(message.layer as usize) {
+            Some(layer) if !layer.locked => rasterise(&mut layer.canvas, message),
+            _ => {}
+        }
+    }
+
+    /// Like [`Layers::rasterise`] for a batch of messages, painting every
+    /// layer with [`crate::rasterise_parallel`].
+    #[cfg(feature = "rayon")]
+    pub fn rasterise_parallel(&mut self, messages: &[ClientMessage]) {
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            if layer.locked {
+                continue;
+            }
+
+            // layers are independent, so only the order within one matters
+            match messages.iter().all(|message| message.layer as usize == i) {
+                true => crate::rasterise_parallel(&mut layer.canvas, messages),
+                false => {
+                    let messages = messages
+                        .iter()
+                        .filter(|message| message.layer as usize == i)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    crate::rasterise_parallel(&mut layer.canvas, &messages);
+                }
+            }
+        }
+    }
+
+    /// The colour of `(x, y)` with every visible layer on top of white, or
+    /// `None` outside of the canvas.
+    pub fn composite_pixel(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        let mut color = [0xFF; 3];
+
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            let alpha = layer.canvas.alpha(x, y)? as u32;
+            let pixel = layer.canvas.pixel(x, y)?;
+
+            for (color, pixel) in color.iter_mut().zip(pixel) {
+                *color = ((pixel as u32 * alpha + *color as u32 * (255 - alpha) + 127) / 255) as u8;
+            }
+        }
+
+        Some(color)
+    }
+
+    /// A copy of the `width` by `height` rectangle at `(x, y)` of every
+    /// layer, or `None` if it is not within the canvas.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Option<Self> {
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| {
+                Some(Layer {
+                    canvas: layer.canvas.crop(x, y, width, height)?,
+                    ..*layer
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self { layers })
+    }
+
+    /// Every visible layer on top of white, as one opaque canvas.
+    pub fn composite(&self) -> Canvas {
+        let (width, height) = (self.width(), self.height());
+        let mut visible = self.layers.iter().filter(|layer| layer.visible).peekable();
+
+        // the bottom layer is opaque, so it is copied instead of blended
+        let mut data = match visible.next_if(|layer| !layer.canvas.is_transparent()) {
+            Some(layer) => layer.canvas.as_bytes().to_vec(),
+            None => vec![0xFF; width * height * 3],
+        };
+        for layer in visible {
+            let alpha = layer.canvas.alpha_bytes().unwrap();
+            let pixels = data
+                .chunks_exact_mut(3)
+                .zip(layer.canvas.as_bytes().chunks_exact(3));
+
+            for ((color, pixel), &alpha) in pixels.zip(alpha) {
+                match alpha {
+                    0 => {}
+                    0xFF => color.copy_from_slice(pixel),
+                    alpha => {
+                        for (color, &pixel) in color.iter_mut().zip(pixel) {
+                            *color = ((pixel as u32 * alpha as u32
+                                + *color as u32 * (255 - alpha as u32)
+                                + 127)
+                                / 255) as u8;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut canvas = Canvas::from_raw(width, height, data).unwrap();
+        canvas.set_antialias(self.antialias());
+
+        canvas
+    }
+}
@@ -0,0 +1,32 @@
+//! The draw-together wire format and software rasteriser.
+//!
+//! This crate has no runtime dependencies so it can be shared between the
+//! server, offline tools and other targets.
+
+mod antialias;
+mod canvas;
+mod compact;
+mod fill;
+mod layers;
+mod message;
+mod polygon;
+mod rasterise;
+mod stamp;
+mod stroke;
+mod text;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use canvas::Canvas;
+pub use compact::{COMPACT, CompactSession, PALETTE_SIZE};
+pub use fill::flood_fill;
+pub use layers::{Layer, Layers};
+pub use message::{
+    Action, Batch, Blend, ClientMessage, DecodeError, MAX_IMAGE_PIXELS, MAX_LAYERS,
+    MAX_POLYGON_SIDES, MAX_SPANS, MAX_STAMP_SCALE, MAX_STROKE_POINTS, MAX_TEXT_LENGTH,
+    MAX_TEXT_SCALE, RESOLUTION_HEIGHT, RESOLUTION_WIDTH, Span, Style,
+};
+pub use rasterise::rasterise;
+#[cfg(feature = "rayon")]
+pub use rasterise::rasterise_parallel;
+pub use stamp::{MAX_STAMP_SIZE, Stamp, stamp};
@@ -0,0 +1,970 @@
+use crate::{compact::COMPACT, text::text_size};
+
+/// With the `serde` feature, actions are objects tagged with their `type`
+/// in snake case, like `{"type": "draw_line", "x2": 10, "y2": 20}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum Action {
+    Erase,
+    DrawCubeNormal,
+    DrawCubeHollow,
+    DrawCircleNormal,
+    DrawCircleHollow,
+    DrawTriangleNormal,
+    DrawTriangleHollow,
+    DrawHexagonNormal,
+    DrawHexagonHollow,
+    /// A freehand brush stroke starting at the message position, `height`
+    /// pixels wide. Every point is a delta from the one before it.
+    Stroke {
+        points: Vec<(i8, i8)>,
+    },
+    /// A straight line to `(x2, y2)`, `height` pixels thick.
+    DrawLine {
+        x2: u16,
+        y2: u16,
+    },
+    /// A rectangle with its top left corner at the message position.
+    DrawRectangleNormal {
+        width: u16,
+        height: u16,
+    },
+    /// Like [`Action::DrawRectangleNormal`], with an outline that is the
+    /// message `height` thick on the inside of the rectangle.
+    DrawRectangleHollow {
+        width: u16,
+        height: u16,
+    },
+    /// An ellipse centred on the message position.
+    DrawEllipseNormal {
+        radius_x: u16,
+        radius_y: u16,
+    },
+    /// Like [`Action::DrawEllipseNormal`], with an outline that is the
+    /// message `height` thick on the inside of the ellipse.
+    DrawEllipseHollow {
+        radius_x: u16,
+        radius_y: u16,
+    },
+    /// Fills the area around the message position that has the same colour.
+    /// Only the server runs this, see [`crate::flood_fill`], and sends the
+    /// result to clients as [`Action::DrawSpans`].
+    FloodFill,
+    /// Horizontal runs of pixels, usually the result of a flood fill.
+    DrawSpans {
+        spans: Vec<Span>,
+    },
+    /// Text in the built-in bitmap font with its top left corner at the
+    /// message position, scaled up by the message `height`. Every `\n`
+    /// starts a new line.
+    DrawText {
+        text: String,
+    },
+    /// Pixels copied onto the canvas row by row, `width` per row, with the
+    /// top left corner at the message position. Pasted images are sent in
+    /// strips of these.
+    DrawImage {
+        width: u16,
+        pixels: Vec<[u8; 3]>,
+    },
+    /// A regular polygon with `sides` corners centred on the message
+    /// position, the message `height` in radius, with the first corner
+    /// pointing up and turned clockwise by `rotation` degrees. A non-zero
+    /// `inner` radius, out of 255 of the outer one, makes it a star.
+    DrawPolygonNormal {
+        sides: u8,
+        rotation: u16,
+        inner: u8,
+    },
+    /// Like [`Action::DrawPolygonNormal`], with a 2 pixel wide outline.
+    DrawPolygonHollow {
+        sides: u8,
+        rotation: u16,
+        inner: u8,
+    },
+    /// One of the stamps the server defines, with its top left corner at the
+    /// message position, scaled up by the message `height` and tinted with
+    /// the message colour. Only the server runs this, see [`crate::stamp`],
+    /// and sends the result to clients as [`Action::DrawSpans`].
+    DrawStamp {
+        id: u16,
+    },
+}
+
+/// `length` pixels to the right of and including `(x, y)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub x: u16,
+    pub y: u16,
+    pub length: u16,
+}
+
+/// How a message is combined with what is already on the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Style {
+    /// 0 leaves the canvas untouched, 255 paints the blended colour as is.
+    pub opacity: u8,
+    pub blend: Blend,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            opacity: 0xFF,
+            blend: Blend::Normal,
+        }
+    }
+}
+
+impl Style {
+    /// Whether painting simply replaces pixels with the message colour.
+    pub fn replaces(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Blend {
+    /// The message colour.
+    #[default]
+    Normal,
+    /// The message colour multiplied with the canvas, which only darkens it,
+    /// like a highlighter.
+    Multiply,
+}
+
+pub const RESOLUTION_WIDTH: usize = 1920;
+pub const RESOLUTION_HEIGHT: usize = 1000;
+
+/// Most points a single stroke message may carry.
+pub const MAX_STROKE_POINTS: usize = 512;
+/// Most spans a single message may carry, so it fits the extended format.
+pub const MAX_SPANS: usize = 8192;
+/// Most characters a single text message may carry.
+pub const MAX_TEXT_LENGTH: usize = 256;
+/// Largest scale text may be drawn at.
+pub const MAX_TEXT_SCALE: u8 = 16;
+/// Most pixels a single image message may carry, so it fits the extended
+/// format.
+pub const MAX_IMAGE_PIXELS: usize = 21_000;
+/// Most corners a regular polygon may have. Stars have twice as many.
+pub const MAX_POLYGON_SIDES: u8 = 64;
+/// Largest scale stamps may be drawn at.
+pub const MAX_STAMP_SCALE: u8 = 8;
+/// Most layers a canvas may have, see [`crate::Layers`].
+pub const MAX_LAYERS: u8 = 8;
+
+const EXTENDED: u8 = 0xF;
+const FLAG_STYLE: u8 = 0x1;
+const FLAG_LAYER: u8 = 0x2;
+const ACTION_STROKE: u8 = 9;
+const ACTION_LINE: u8 = 10;
+const ACTION_RECTANGLE_NORMAL: u8 = 11;
+const ACTION_RECTANGLE_HOLLOW: u8 = 12;
+const ACTION_ELLIPSE_NORMAL: u8 = 13;
+const ACTION_ELLIPSE_HOLLOW: u8 = 14;
+const ACTION_FLOOD_FILL: u8 = 15;
+const ACTION_SPANS: u8 = 16;
+const ACTION_TEXT: u8 = 17;
+const ACTION_IMAGE: u8 = 18;
+const ACTION_POLYGON_NORMAL: u8 = 19;
+const ACTION_POLYGON_HOLLOW: u8 = 20;
+const ACTION_STAMP: u8 = 21;
+
+// binary format:
+// (4b) action  | byte 1
+// (4b) height  | byte 1
+//
+// (3b) height  | byte 2
+// (5b) x       | byte 2
+//
+// (6b) x       | byte 3
+// (2b) y       | byte 3
+//
+// (8b) y       | byte 4
+//
+// (8b) color[0]| byte 5
+// (8b) color[1]| byte 6
+// (8b) color[2]| byte 7
+//
+// extended format, for actions that do not fit into 7 bytes:
+// (4b) 0xF     | byte 1
+// (4b) flags   | byte 1, 0x1 = style, 0x2 = layer, others must be 0
+//
+// (8b) action  | byte 2
+//
+// (16b) length | byte 3-4, of everything after byte 4
+//
+// (16b) x      | byte 5-6
+// (16b) y      | byte 7-8
+// (8b) height  | byte 9
+// (24b) color  | byte 10-12
+//
+// if the style flag is set:
+// (8b) opacity
+// (8b) blend, 0 = normal, 1 = multiply
+//
+// if the layer flag is set:
+// (8b) layer, otherwise the first one
+//
+// followed by action specific data:
+// actions 0-8: nothing, for the legacy actions with a style or layer
+// stroke: (8b) dx, (8b) dy per point, both signed
+// line: (16b) x2, (16b) y2
+// rectangle: (16b) width, (16b) height
+// ellipse: (16b) radius x, (16b) radius y
+// flood fill: nothing
+// spans: (16b) x, (16b) y, (16b) length per span
+// text: the UTF-8 string, with height as the scale
+// image: (16b) width, (24b) color per pixel, row by row
+// polygon: (8b) sides, (16b) rotation in degrees, (8b) inner radius
+// stamp: (16b) id, with height as the scale
+
+/// With the `serde` feature, `style` and `layer` may be left out for the
+/// defaults. Messages from there are checked with
+/// [`ClientMessage::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientMessage {
+    pub action: Action,
+
+    pub x: u16,
+    pub y: u16,
+
+    pub height: u8,
+    pub color: [u8; 3],
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub style: Style,
+    /// The layer painted on, 0 is the bottom one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub layer: u8,
+}
+
+/// The messages of a websocket frame, see
+/// [`ClientMessage::decode_batch_checked`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Batch {
+    pub messages: Vec<ClientMessage>,
+    /// Why messages were skipped, in the order they appeared.
+    pub errors: Vec<DecodeError>,
+}
+
+/// Why a message could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DecodeError {
+    /// The data ends before the message does.
+    Truncated,
+    /// The data goes on after the message, see [`ClientMessage::decode`].
+    TrailingBytes,
+    /// The action code is not known.
+    UnknownAction,
+    /// Header flags that are reserved are set.
+    ReservedFlags,
+    /// The payload does not have the length the action needs.
+    InvalidLength,
+    /// The height, or width, is zero.
+    ZeroHeight,
+    /// The height is larger than the action allows.
+    HeightTooLarge,
+    /// The position, or something painted relative to it, is outside of the
+    /// canvas.
+    OutOfBounds,
+    /// The action carries more points, spans, characters or pixels than
+    /// allowed.
+    TooLarge,
+    /// A field has a value the action does not allow, like an unknown blend
+    /// mode or layer, a zero size or text that is not UTF-8.
+    InvalidValue,
+}
+
+impl DecodeError {
+    pub const ALL: [Self; 10] = [
+        Self::Truncated,
+        Self::TrailingBytes,
+        Self::UnknownAction,
+        Self::ReservedFlags,
+        Self::InvalidLength,
+        Self::ZeroHeight,
+        Self::HeightTooLarge,
+        Self::OutOfBounds,
+        Self::TooLarge,
+        Self::InvalidValue,
+    ];
+
+    /// A short snake case name, for logs and metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Truncated => "truncated",
+            Self::TrailingBytes => "trailing_bytes",
+            Self::UnknownAction => "unknown_action",
+            Self::ReservedFlags => "reserved_flags",
+            Self::InvalidLength => "invalid_length",
+            Self::ZeroHeight => "zero_height",
+            Self::HeightTooLarge => "height_too_large",
+            Self::OutOfBounds => "out_of_bounds",
+            Self::TooLarge => "too_large",
+            Self::InvalidValue => "invalid_value",
+        }
+    }
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Truncated => "message is truncated",
+            Self::TrailingBytes => "bytes after the message",
+            Self::UnknownAction => "unknown action",
+            Self::ReservedFlags => "reserved flags are set",
+            Self::InvalidLength => "invalid payload length",
+            Self::ZeroHeight => "height is zero",
+            Self::HeightTooLarge => "height is too large",
+            Self::OutOfBounds => "outside of the canvas",
+            Self::TooLarge => "too many points, spans, characters or pixels",
+            Self::InvalidValue => "invalid value",
+        })
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl ClientMessage {
+    /// Decodes a single message that spans all of `data`.
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        match Self::decode_next(data) {
+            Some((message, len)) if len == data.len() => message,
+            Some((message, _)) => message.and(Err(DecodeError::TrailingBytes)),
+            None => Err(DecodeError::Truncated),
+        }
+    }
+
+    /// Checks a message that was not decoded, e.g. one built from JSON, by
+    /// the same rules as decoding.
+    pub fn validate(&self) -> Result<(), DecodeError> {
+        if self.x >= RESOLUTION_WIDTH as u16 || self.y >= RESOLUTION_HEIGHT as u16 {
+            return Err(DecodeError::OutOfBounds);
+        }
+
+        let encoded = self.encode();
+        if encoded.len() > 4 + u16::MAX as usize {
+            return Err(DecodeError::TooLarge);
+        }
+
+        // anything else that does not survive encoding does not fit the format
+        match Self::decode(&encoded) {
+            Ok(decoded) if decoded == *self => Ok(()),
+            Ok(decoded) if decoded.height != self.height => Err(DecodeError::HeightTooLarge),
+            Ok(_) => Err(DecodeError::InvalidValue),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Decodes every message in a websocket frame, skipping invalid ones.
+    pub fn decode_batch(data: &[u8]) -> Vec<Self> {
+        Self::decode_batch_checked(data).messages
+    }
+
+    /// Like [`ClientMessage::decode_batch`], also returning why messages
+    /// were skipped. A compact frame is rejected as a whole, as its records
+    /// would be misread as messages, see [`crate::CompactSession::decode`].
+    pub fn decode_batch_checked(mut data: &[u8]) -> Batch {
+        if data.first() == Some(&COMPACT) {
+            return Batch {
+                messages: Vec::new(),
+                errors: vec![DecodeError::UnknownAction],
+            };
+        }
+
+        let mut batch = Batch {
+            messages: Vec::with_capacity(data.len() / 7),
+            ..Batch::default()
+        };
+
+        while let Some((message, len)) = Self::decode_next(data) {
+            match message {
+                Ok(message) => batch.messages.push(message),
+                Err(err) => batch.errors.push(err),
+            }
+            data = &data[len..];
+        }
+        if !data.is_empty() {
+            batch.errors.push(DecodeError::Truncated);
+        }
+
+        batch
+    }
+
+    /// Reads the message at the start of `data` and returns it, or why it
+    /// is invalid, together with its length. Returns `None` if `data` is
+    /// too short to hold a whole message.
+    pub(crate) fn decode_next(data: &[u8]) -> Option<(Result<Self, DecodeError>, usize)> {
+        if (data.first()? >> 4) & 0xF == EXTENDED {
+            if data.len() < 4 {
+                return None;
+            }
+
+            let len = 4 + u16::from_be_bytes([data[2], data[3]]) as usize;
+            if data.len() < len {
+                return None;
+            }
+
+            return Some((
+                Self::decode_extended(data[0] & 0xF, data[1], &data[4..len]),
+                len,
+            ));
+        }
+
+        if data.len() < 7 {
+            return None;
+        }
+
+        Some((Self::decode_legacy(&data[..7]), 7))
+    }
+
+    fn decode_legacy(data: &[u8]) -> Result<Self, DecodeError> {
+        let action = legacy_action((data[0] >> 4) & 0xF).ok_or(DecodeError::UnknownAction)?;
+
+        let height_high = data[0] & 0xF;
+        let height_low = (data[1] >> 5) & 0x7;
+        let height = (height_high << 3) | height_low;
+        let x_high = data[1] & 0x1F;
+        let x_low = (data[2] >> 2) & 0x3F;
+        let x = ((x_high as u16) << 6) | (x_low as u16);
+        let y_high = data[2] & 0x3;
+        let y = ((y_high as u16) << 8) | (data[3] as u16);
+        let color = [data[4], data[5], data[6]];
+
+        Self::validated(action, x, y, height, color, Style::default(), 0)
+    }
+
+    fn decode_extended(flags: u8, action: u8, payload: &[u8]) -> Result<Self, DecodeError> {
+        if flags & !(FLAG_STYLE | FLAG_LAYER) != 0 {
+            return Err(DecodeError::ReservedFlags);
+        }
+        if payload.len() < 8 {
+            return Err(DecodeError::InvalidLength);
+        }
+
+        let x = u16::from_be_bytes([payload[0], payload[1]]);
+        let y = u16::from_be_bytes([payload[2], payload[3]]);
+        let height = payload[4];
+        let color = [payload[5], payload[6], payload[7]];
+        let mut data = &payload[8..];
+
+        let mut style = Style::default();
+        if flags & FLAG_STYLE != 0 {
+            let [opacity, blend, rest @ ..] = data else {
+                return Err(DecodeError::InvalidLength);
+            };
+
+            style.opacity = *opacity;
+            style.blend = match blend {
+                0 => Blend::Normal,
+                1 => Blend::Multiply,
+                _ => return Err(DecodeError::InvalidValue),
+            };
+            data = rest;
+        }
+
+        let mut layer = 0;
+        if flags & FLAG_LAYER != 0 {
+            let [value, rest @ ..] = data else {
+                return Err(DecodeError::InvalidLength);
+            };
+            if *value >= MAX_LAYERS {
+                return Err(DecodeError::InvalidValue);
+            }
+
+            layer = *value;
+            data = rest;
+        }
+
+        let action = match action {
+            // the legacy actions keep their 7 bit height
+            0..=8 => {
+                if !data.is_empty() {
+                    return Err(DecodeError::InvalidLength);
+                }
+                if height > 0x7F {
+                    return Err(DecodeError::HeightTooLarge);
+                }
+
+                legacy_action(action).ok_or(DecodeError::UnknownAction)?
+            }
+            ACTION_STROKE => {
+                if !data.len().is_multiple_of(2) {
+                    return Err(DecodeError::InvalidLength);
+                }
+                if data.len() / 2 > MAX_STROKE_POINTS {
+                    return Err(DecodeError::TooLarge);
+                }
+
+                Action::Stroke {
+                    points: data
+                        .chunks(2)
+                        .map(|point| (point[0] as i8, point[1] as i8))
+                        .collect(),
+                }
+            }
+            ACTION_LINE => {
+                let (x2, y2) = decode_pair(data)?;
+                if x2 >= RESOLUTION_WIDTH as u16 || y2 >= RESOLUTION_HEIGHT as u16 {
+                    return Err(DecodeError::OutOfBounds);
+                }
+
+                Action::DrawLine { x2, y2 }
+            }
+            ACTION_RECTANGLE_NORMAL | ACTION_RECTANGLE_HOLLOW => {
+                let (width, height) = decode_pair(data)?;
+                if width == 0 || height == 0 {
+                    return Err(DecodeError::InvalidValue);
+                }
+                if width > RESOLUTION_WIDTH as u16 || height > RESOLUTION_HEIGHT as u16 {
+                    return Err(DecodeError::OutOfBounds);
+                }
+
+                match action {
+                    ACTION_RECTANGLE_NORMAL => Action::DrawRectangleNormal { width, height },
+                    _ => Action::DrawRectangleHollow { width, height },
+                }
+            }
+            ACTION_ELLIPSE_NORMAL | ACTION_ELLIPSE_HOLLOW => {
+                let (radius_x, radius_y) = decode_pair(data)?;
+                if radius_x == 0 || radius_y == 0 {
+                    return Err(DecodeError::InvalidValue);
+                }
+                if radius_x > RESOLUTION_WIDTH as u16 || radius_y > RESOLUTION_HEIGHT as u16 {
+                    return Err(DecodeError::OutOfBounds);
+                }
+
+                match action {
+                    ACTION_ELLIPSE_NORMAL => Action::DrawEllipseNormal { radius_x, radius_y },
+                    _ => Action::DrawEllipseHollow { radius_x, radius_y },
+                }
+            }
+            ACTION_FLOOD_FILL => {
+                if !data.is_empty() {
+                    return Err(DecodeError::InvalidLength);
+                }
+
+                Action::FloodFill
+            }
+            ACTION_SPANS => {
+                if !data.len().is_multiple_of(6) {
+                    return Err(DecodeError::InvalidLength);
+                }
+                if data.len() / 6 > MAX_SPANS {
+                    return Err(DecodeError::TooLarge);
+                }
+
+                let mut spans = Vec::with_capacity(data.len() / 6);
+                for span in data.chunks(6) {
+                    let span = Span {
+                        x: u16::from_be_bytes([span[0], span[1]]),
+                        y: u16::from_be_bytes([span[2], span[3]]),
+                        length: u16::from_be_bytes([span[4], span[5]]),
+                    };
+
+                    if span.length == 0 {
+                        return Err(DecodeError::InvalidValue);
+                    }
+                    if span.x as usize + span.length as usize > RESOLUTION_WIDTH
+                        || span.y as usize >= RESOLUTION_HEIGHT
+                    {
+                        return Err(DecodeError::OutOfBounds);
+                    }
+
+                    spans.push(span);
+                }
+
+                Action::DrawSpans { spans }
+            }
+            ACTION_TEXT => {
+                let text = std::str::from_utf8(data).map_err(|_| DecodeError::InvalidValue)?;
+                if text.is_empty() {
+                    return Err(DecodeError::InvalidLength);
+                }
+                if text.chars().count() > MAX_TEXT_LENGTH {
+                    return Err(DecodeError::TooLarge);
+                }
+                if height > MAX_TEXT_SCALE {
+                    return Err(DecodeError::HeightTooLarge);
+                }
+
+                Action::DrawText {
+                    text: text.to_owned(),
+                }
+            }
+            ACTION_IMAGE => {
+                let [width_high, width_low, pixels @ ..] = data else {
+                    return Err(DecodeError::InvalidLength);
+                };
+                let width = u16::from_be_bytes([*width_high, *width_low]) as usize;
+                if width == 0 {
+                    return Err(DecodeError::InvalidValue);
+                }
+                if pixels.is_empty()
+                    || !pixels.len().is_multiple_of(3)
+                    || !(pixels.len() / 3).is_multiple_of(width)
+                {
+                    return Err(DecodeError::InvalidLength);
+                }
+                if pixels.len() / 3 > MAX_IMAGE_PIXELS {
+                    return Err(DecodeError::TooLarge);
+                }
+                if x as usize + width > RESOLUTION_WIDTH
+                    || y as usize + pixels.len() / 3 / width > RESOLUTION_HEIGHT
+                {
+                    return Err(DecodeError::OutOfBounds);
+                }
+
+                Action::DrawImage {
+                    width: width as u16,
+                    pixels: pixels
+                        .chunks(3)
+                        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                        .collect(),
+                }
+            }
+            ACTION_POLYGON_NORMAL | ACTION_POLYGON_HOLLOW => {
+                let &[sides, rotation_high, rotation_low, inner] = data else {
+                    return Err(DecodeError::InvalidLength);
+                };
+                let rotation = u16::from_be_bytes([rotation_high, rotation_low]);
+                if !(3..=MAX_POLYGON_SIDES).contains(&sides) || rotation >= 360 {
+                    return Err(DecodeError::InvalidValue);
+                }
+
+                match action {
+                    ACTION_POLYGON_NORMAL => Action::DrawPolygonNormal {
+                        sides,
+                        rotation,
+                        inner,
+                    },
+                    _ => Action::DrawPolygonHollow {
+                        sides,
+                        rotation,
+                        inner,
+                    },
+                }
+            }
+            ACTION_STAMP => {
+                let &[id_high, id_low] = data else {
+                    return Err(DecodeError::InvalidLength);
+                };
+                if height > MAX_STAMP_SCALE {
+                    return Err(DecodeError::HeightTooLarge);
+                }
+
+                Action::DrawStamp {
+                    id: u16::from_be_bytes([id_high, id_low]),
+                }
+            }
+            _ => return Err(DecodeError::UnknownAction),
+        };
+
+        Self::validated(action, x, y, height, color, style, layer)
+    }
+
+    fn validated(
+        action: Action,
+        x: u16,
+        y: u16,
+        height: u8,
+        color: [u8; 3],
+        style: Style,
+        layer: u8,
+    ) -> Result<Self, DecodeError> {
+        if height == 0 {
+            return Err(DecodeError::ZeroHeight);
+        }
+        if x >= RESOLUTION_WIDTH as u16 || y >= RESOLUTION_HEIGHT as u16 {
+            return Err(DecodeError::OutOfBounds);
+        }
+
+        Ok(Self {
+            action,
+            x,
+            y,
+            height,
+            color,
+            style,
+            layer,
+        })
+    }
+
+    /// Inclusive `(min_x, min_y, max_x, max_y)` of every pixel this message
+    /// can touch, before clipping to the canvas.
+    pub fn bounds(&self) -> (i32, i32, i32, i32) {
+        let x = self.x as i32;
+        let y = self.y as i32;
+        let height = self.height as i32;
+
+        match &self.action {
+            Action::Erase => {
+                let height = (self.height as f64 * 1.5) as i32;
+                (x, y, x + height, y + height)
+            }
+            Action::DrawCubeNormal => (x, y, x + height, y + height),
+            Action::DrawCubeHollow => (x, y, x + height + 1, y + height + 1),
+            Action::DrawCircleNormal | Action::DrawCircleHollow => {
+                (x - height, y - height, x + height, y + height)
+            }
+            Action::DrawTriangleNormal | Action::DrawTriangleHollow => {
+                (x - height, y, x + height + 1, y + height * 2 + 1)
+            }
+            Action::DrawHexagonNormal
+            | Action::DrawHexagonHollow
+            | Action::DrawPolygonNormal { .. }
+            | Action::DrawPolygonHollow { .. } => {
+                (x - height, y - height, x + height + 1, y + height + 1)
+            }
+            Action::Stroke { points } => {
+                let (mut min_x, mut min_y, mut max_x, mut max_y) = (x, y, x, y);
+                let (mut point_x, mut point_y) = (x, y);
+
+                for (dx, dy) in points {
+                    point_x += *dx as i32;
+                    point_y += *dy as i32;
+
+                    min_x = min_x.min(point_x);
+                    min_y = min_y.min(point_y);
+                    max_x = max_x.max(point_x);
+                    max_y = max_y.max(point_y);
+                }
+
+                let radius = height / 2 + 1;
+                (
+                    min_x - radius,
+                    min_y - radius,
+                    max_x + radius,
+                    max_y + radius,
+                )
+            }
+            Action::DrawLine { x2, y2 } => {
+                let (x2, y2) = (*x2 as i32, *y2 as i32);
+                let radius = height / 2 + 1;
+
+                (
+                    x.min(x2) - radius,
+                    y.min(y2) - radius,
+                    x.max(x2) + radius,
+                    y.max(y2) + radius,
+                )
+            }
+            Action::DrawRectangleNormal { width, height }
+            | Action::DrawRectangleHollow { width, height } => {
+                (x, y, x + *width as i32 - 1, y + *height as i32 - 1)
+            }
+            Action::DrawEllipseNormal { radius_x, radius_y }
+            | Action::DrawEllipseHollow { radius_x, radius_y } => {
+                let (radius_x, radius_y) = (*radius_x as i32, *radius_y as i32);
+                (x - radius_x, y - radius_y, x + radius_x, y + radius_y)
+            }
+            Action::FloodFill | Action::DrawStamp { .. } => (x, y, x, y),
+            Action::DrawSpans { spans } => {
+                spans
+                    .iter()
+                    .fold((x, y, x, y), |(min_x, min_y, max_x, max_y), span| {
+                        let (span_x, span_y) = (span.x as i32, span.y as i32);
+
+                        (
+                            min_x.min(span_x),
+                            min_y.min(span_y),
+                            max_x.max(span_x + span.length as i32 - 1),
+                            max_y.max(span_y),
+                        )
+                    })
+            }
+            Action::DrawText { text } => {
+                let (width, height) = text_size(text, self.height);
+                (x, y, x + width as i32 - 1, y + height as i32 - 1)
+            }
+            Action::DrawImage { width, pixels } => {
+                let width = *width as i32;
+                let rows = pixels.len() as i32 / width.max(1);
+
+                (x, y, x + width - 1, y + rows - 1)
+            }
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let action_value = match &self.action {
+            Action::Erase => 0,
+            Action::DrawCubeNormal => 1,
+            Action::DrawCubeHollow => 2,
+            Action::DrawCircleNormal => 3,
+            Action::DrawCircleHollow => 4,
+            Action::DrawTriangleNormal => 5,
+            Action::DrawTriangleHollow => 6,
+            Action::DrawHexagonNormal => 7,
+            Action::DrawHexagonHollow => 8,
+            Action::Stroke { points } => {
+                return self.encode_extended(ACTION_STROKE, |buf| {
+                    buf.extend(points.iter().flat_map(|(dx, dy)| [*dx as u8, *dy as u8]));
+                });
+            }
+            Action::DrawLine { x2, y2 } => {
+                return self.encode_extended(ACTION_LINE, |buf| encode_pair(buf, *x2, *y2));
+            }
+            Action::DrawRectangleNormal { width, height } => {
+                return self.encode_extended(ACTION_RECTANGLE_NORMAL, |buf| {
+                    encode_pair(buf, *width, *height)
+                });
+            }
+            Action::DrawRectangleHollow { width, height } => {
+                return self.encode_extended(ACTION_RECTANGLE_HOLLOW, |buf| {
+                    encode_pair(buf, *width, *height)
+                });
+            }
+            Action::DrawEllipseNormal { radius_x, radius_y } => {
+                return self.encode_extended(ACTION_ELLIPSE_NORMAL, |buf| {
+                    encode_pair(buf, *radius_x, *radius_y)
+                });
+            }
+            Action::DrawEllipseHollow { radius_x, radius_y } => {
+                return self.encode_extended(ACTION_ELLIPSE_HOLLOW, |buf| {
+                    encode_pair(buf, *radius_x, *radius_y)
+                });
+            }
+            Action::FloodFill => return self.encode_extended(ACTION_FLOOD_FILL, |_| {}),
+            Action::DrawSpans { spans } => {
+                return self.encode_extended(ACTION_SPANS, |buf| {
+                    for span in spans {
+                        encode_pair(buf, span.x, span.y);
+                        buf.extend(span.length.to_be_bytes());
+                    }
+                });
+            }
+            Action::DrawText { text } => {
+                return self.encode_extended(ACTION_TEXT, |buf| buf.extend(text.as_bytes()));
+            }
+            Action::DrawImage { width, pixels } => {
+                return self.encode_extended(ACTION_IMAGE, |buf| {
+                    buf.extend(width.to_be_bytes());
+                    buf.extend(pixels.iter().flatten());
+                });
+            }
+            Action::DrawPolygonNormal {
+                sides,
+                rotation,
+                inner,
+            } => {
+                return self.encode_extended(ACTION_POLYGON_NORMAL, |buf| {
+                    encode_polygon(buf, *sides, *rotation, *inner)
+                });
+            }
+            Action::DrawPolygonHollow {
+                sides,
+                rotation,
+                inner,
+            } => {
+                return self.encode_extended(ACTION_POLYGON_HOLLOW, |buf| {
+                    encode_polygon(buf, *sides, *rotation, *inner)
+                });
+            }
+            Action::DrawStamp { id } => {
+                return self.encode_extended(ACTION_STAMP, |buf| buf.extend(id.to_be_bytes()));
+            }
+        };
+
+        if !self.style.replaces() || self.layer != 0 {
+            return self.encode_extended(action_value, |_| {});
+        }
+
+        let mut buf = vec![0; 7];
+
+        buf[0] = (action_value << 4) | ((self.height >> 3) & 0xF);
+        buf[1] = ((self.height & 0x7) << 5) | ((self.x >> 6) as u8 & 0x1F);
+        buf[2] = (((self.x & 0x3F) << 2) | ((self.y >> 8) & 0x3)) as u8;
+        buf[3] = self.y as u8;
+        buf[4..].copy_from_slice(&self.color);
+
+        buf
+    }
+
+    fn encode_extended(&self, action: u8, data: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(14);
+
+        let mut flags = 0;
+        if !self.style.replaces() {
+            flags |= FLAG_STYLE;
+        }
+        if self.layer != 0 {
+            flags |= FLAG_LAYER;
+        }
+        buf.extend([(EXTENDED << 4) | flags, action, 0, 0]);
+        buf.extend(self.x.to_be_bytes());
+        buf.extend(self.y.to_be_bytes());
+        buf.push(self.height);
+        buf.extend(self.color);
+        if !self.style.replaces() {
+            buf.push(self.style.opacity);
+            buf.push(match self.style.blend {
+                Blend::Normal => 0,
+                Blend::Multiply => 1,
+            });
+        }
+        if self.layer != 0 {
+            buf.push(self.layer);
+        }
+        data(&mut buf);
+
+        let len = (buf.len() - 4) as u16;
+        buf[2..4].copy_from_slice(&len.to_be_bytes());
+
+        buf
+    }
+}
+
+fn legacy_action(action: u8) -> Option<Action> {
+    Some(match action {
+        0 => Action::Erase,
+        1 => Action::DrawCubeNormal,
+        2 => Action::DrawCubeHollow,
+        3 => Action::DrawCircleNormal,
+        4 => Action::DrawCircleHollow,
+        5 => Action::DrawTriangleNormal,
+        6 => Action::DrawTriangleHollow,
+        7 => Action::DrawHexagonNormal,
+        8 => Action::DrawHexagonHollow,
+        _ => return None,
+    })
+}
+
+fn decode_pair(data: &[u8]) -> Result<(u16, u16), DecodeError> {
+    let &[a_high, a_low, b_high, b_low] = data else {
+        return Err(DecodeError::InvalidLength);
+    };
+
+    Ok((
+        u16::from_be_bytes([a_high, a_low]),
+        u16::from_be_bytes([b_high, b_low]),
+    ))
+}
+
+fn encode_pair(buf: &mut Vec<u8>, a: u16, b: u16) {
+    buf.extend(a.to_be_bytes());
+    buf.extend(b.to_be_bytes());
+}
+
+fn encode_polygon(buf: &mut Vec<u8>, sides: u8, rotation: u16, inner: u8) {
+    buf.push(sides);
+    buf.extend(rotation.to_be_bytes());
+    buf.push(inner);
+}
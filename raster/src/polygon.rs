@@ -0,0 +1,137 @@
+use crate::{ClientMessage, canvas::Band};
+
+/// The corners of a regular polygon around `center`, the first one pointing
+/// straight up and turned clockwise by `rotation` degrees. With an `inner`
+/// radius every side gets a dent towards the centre, which makes a star.
+pub(crate) fn regular_polygon(
+    center: (f32, f32),
+    radius: f32,
+    sides: u8,
+    rotation: u16,
+    inner: Option<f32>,
+) -> Vec<(f32, f32)> {
+    let corners = match inner {
+        Some(_) => sides as usize * 2,
+        None => sides as usize,
+    };
+    let step = std::f32::consts::TAU / corners as f32;
+    let start = (rotation as f32).to_radians() - std::f32::consts::FRAC_PI_2;
+
+    (0..corners)
+        .map(|i| {
+            let radius = match inner {
+                Some(inner) if i % 2 == 1 => inner,
+                _ => radius,
+            };
+            let angle = start + step * i as f32;
+
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+/// Paints every pixel whose centre lies inside or on the edge of the polygon,
+/// using the even-odd rule so stars come out right.
+pub(crate) fn fill_polygon(band: &mut Band, message: &ClientMessage, points: &[(f32, f32)]) {
+    let min_y = points.iter().map(|(_, y)| *y).fold(f32::MAX, f32::min);
+    let max_y = points.iter().map(|(_, y)| *y).fold(f32::MIN, f32::max);
+    if max_y < 0.0 {
+        return;
+    }
+
+    let mut intersections = Vec::with_capacity(points.len());
+    let mut spans = Vec::with_capacity(points.len());
+    for y in band.rows(min_y.ceil().max(0.0) as usize, max_y.floor() as usize) {
+        let y = y as f32;
+
+        intersections.clear();
+        spans.clear();
+        for i in 0..points.len() {
+            let start = points[i];
+            let end = points[(i + 1) % points.len()];
+
+            if (start.1 <= y && end.1 > y) || (end.1 <= y && start.1 > y) {
+                intersections.push(start.0 + (y - start.1) * (end.0 - start.0) / (end.1 - start.1));
+            } else if start.1 == y && end.1 == y {
+                // edges along the row are not crossed, but are still part of it
+                spans.push((start.0.min(end.0), start.0.max(end.0)));
+            }
+        }
+
+        intersections.sort_unstable_by(f32::total_cmp);
+        spans.extend(intersections.chunks_exact(2).map(|span| (span[0], span[1])));
+        spans.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        // spans can touch at a corner, so paint every column at most once
+        let mut next = 0;
+        for &(start, end) in &spans {
+            let start = start.ceil().max(0.0).max(next as f32);
+            let end = end.floor().min(band.width as f32 - 1.0);
+            if start > end {
+                continue;
+            }
+
+            for x in start as usize..=end as usize {
+                band.set(x, y as usize, &message.color);
+            }
+            next = end as usize + 1;
+        }
+    }
+}
+
+/// Paints a 2 pixel wide outline along the edges of the polygon.
+pub(crate) fn outline_polygon(band: &mut Band, message: &ClientMessage, points: &[(f32, f32)]) {
+    for i in 0..points.len() {
+        let start = points[i];
+        let end = points[(i + 1) % points.len()];
+
+        draw_line_fast(
+            band,
+            start.0 as i32,
+            start.1 as i32,
+            end.0 as i32,
+            end.1 as i32,
+            &message.color,
+        );
+    }
+}
+
+#[inline(always)]
+fn draw_line_fast(band: &mut Band, x1: i32, y1: i32, x2: i32, y2: i32, color: &[u8; 3]) {
+    draw_single_line(band, x1, y1, x2, y2, color);
+    draw_single_line(band, x1 + 1, y1, x2 + 1, y2, color);
+    draw_single_line(band, x1, y1 + 1, x2, y2 + 1, color);
+    draw_single_line(band, x1 + 1, y1 + 1, x2 + 1, y2 + 1, color);
+}
+
+#[inline(always)]
+fn draw_single_line(band: &mut Band, mut x1: i32, mut y1: i32, x2: i32, y2: i32, color: &[u8; 3]) {
+    let dx = (x2 - x1).abs();
+    let dy = -(y2 - y1).abs();
+    let sx = if x1 < x2 { 1 } else { -1 };
+    let sy = if y1 < y2 { 1 } else { -1 };
+    let mut err = dx + dy;
+
+    loop {
+        if x1 >= 0 && x1 < band.width as i32 && y1 >= 0 && band.contains_row(y1 as usize) {
+            band.set(x1 as usize, y1 as usize, color);
+        }
+
+        if x1 == x2 && y1 == y2 {
+            break;
+        }
+
+        let e2 = err * 2;
+        if e2 >= dy {
+            err += dy;
+            x1 += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y1 += sy;
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    Action, ClientMessage, antialias,
+    canvas::{Band, Canvas},
+    polygon::{fill_polygon, outline_polygon, regular_polygon},
+    stroke::{draw_line, draw_stroke},
+    text::draw_text,
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Rows per band when painting with [`rasterise_parallel`].
+#[cfg(feature = "rayon")]
+const BAND_HEIGHT: usize = 50;
+
+/// Paints a single message onto the canvas.
+pub fn rasterise(canvas: &mut Canvas, message: &ClientMessage) {
+    rasterise_band(&mut canvas.band(), message);
+}
+
+/// Paints a batch of messages, splitting the canvas into row bands that are
+/// painted on the rayon pool. The result is identical to calling
+/// [`rasterise`] for every message in order.
+#[cfg(feature = "rayon")]
+pub fn rasterise_parallel(canvas: &mut Canvas, messages: &[ClientMessage]) {
+    canvas.bands(BAND_HEIGHT).for_each(|mut band| {
+        for message in messages {
+            rasterise_band(&mut band, message);
+        }
+    });
+}
+
+fn rasterise_band(band: &mut Band, message: &ClientMessage) {
+    let bounds = message.bounds();
+    let (_, min_y, _, max_y) = bounds;
+    if max_y < band.start_y as i32 || min_y >= band.end_y as i32 {
+        return;
+    }
+
+    band.set_style(message.style, bounds);
+
+    match &message.action {
+        Action::Erase => {
+            let height = (message.height as f64) * 1.5;
+
+            let start_x = message.x as usize;
+            let end_x = ((message.x + height as u16).min(band.width as u16 - 1)) as usize;
+            let start_y = message.y as usize;
+            let end_y = ((message.y + height as u16).min(band.height as u16 - 1)) as usize;
+
+            for y in band.rows(start_y, end_y) {
+                for x in start_x..=end_x {
+                    band.clear(x, y);
+                }
+            }
+        }
+        Action::DrawCubeNormal => {
+            let height = message.height as usize;
+
+            let start_x = message.x as usize;
+            let end_x = ((message.x + height as u16).min(band.width as u16 - 1)) as usize;
+            let start_y = message.y as usize;
+            let end_y = ((message.y + height as u16).min(band.height as u16 - 1)) as usize;
+
+            for y in band.rows(start_y, end_y) {
+                for x in start_x..=end_x {
+                    band.set(x, y, &message.color);
+                }
+            }
+        }
+        Action::DrawCubeHollow => {
+            let height = message.height as usize;
+
+            let start_x = message.x as usize;
+            let end_x = ((message.x + height as u16).min(band.width as u16 - 1)) as usize;
+            let start_y = message.y as usize;
+            let end_y = ((message.y + height as u16).min(band.height as u16 - 1)) as usize;
+
+            // the far edges are clamped onto the canvas, which must not pull
+            // in a square that starts entirely outside of it
+            if start_x >= band.width || start_y >= band.height {
+                return;
+            }
+
+            for offset in 0..2 {
+                for x in start_x..=end_x {
+                    if band.contains_row(start_y + offset) {
+                        band.set(x, start_y + offset, &message.color);
+                    }
+
+                    if band.contains_row(end_y + offset) {
+                        band.set(x, end_y + offset, &message.color);
+                    }
+                }
+            }
+
+            for offset in 0..2 {
+                for y in band.rows(start_y, end_y) {
+                    if (start_x + offset) < band.width {
+                        band.set(start_x + offset, y, &message.color);
+                    }
+
+                    if (end_x + offset) < band.width {
+                        band.set(end_x + offset, y, &message.color);
+                    }
+                }
+            }
+        }
+        Action::DrawCircleNormal | Action::DrawCircleHollow => {
+            let radius = message.height as usize;
+            let is_hollow = matches!(message.action, Action::DrawCircleHollow);
+
+            if band.antialias {
+                return antialias::draw_circle(band, message, is_hollow);
+            }
+
+            let start_x = message.x.saturating_sub(radius as u16) as usize;
+            let end_x = ((message.x + radius as u16).min(band.width as u16 - 1)) as usize;
+            let start_y = message.y.saturating_sub(radius as u16) as usize;
+            let end_y = ((message.y + radius as u16).min(band.height as u16 - 1)) as usize;
+
+            let center_x = message.x as f32;
+            let center_y = message.y as f32;
+            let radius_sq = (radius * radius) as f32;
+
+            if is_hollow {
+                let outer_radius_sq = radius_sq;
+                // signed so radius 1 keeps its single pixel ring instead of underflowing
+                let inner_radius = radius as f32 - 2.0;
+                let inner_radius_sq = inner_radius * inner_radius;
+
+                for y in band.rows(start_y, end_y) {
+                    let dy = y as f32 - center_y;
+                    let dy_sq = dy * dy;
+
+                    for x in start_x..=end_x {
+                        let dx = x as f32 - center_x;
+                        let dist_sq = dx * dx + dy_sq;
+
+                        if dist_sq <= outer_radius_sq && dist_sq >= inner_radius_sq {
+                            band.set(x, y, &message.color);
+                        }
+                    }
+                }
+            } else {
+                for y in band.rows(start_y, end_y) {
+                    let dy = y as f32 - center_y;
+                    let dy_sq = dy * dy;
+
+                    for x in start_x..=end_x {
+                        let dx = x as f32 - center_x;
+                        let dist_sq = dx * dx + dy_sq;
+
+                        if dist_sq <= radius_sq {
+                            band.set(x, y, &message.color);
+                        }
+                    }
+                }
+            }
+        }
+        Action::DrawTriangleNormal | Action::DrawTriangleHollow => {
+            let height = message.height as f32;
+            let (x, y) = (message.x as f32, message.y as f32);
+
+            let points = [
+                (x, y),
+                (x - height, y + height * 2.0),
+                (x + height, y + height * 2.0),
+            ];
+            draw_polygon(
+                band,
+                message,
+                &points,
+                message.action == Action::DrawTriangleHollow,
+            );
+        }
+        Action::DrawHexagonNormal | Action::DrawHexagonHollow => {
+            let size = message.height as f32;
+            let center_x = message.x as f32;
+            let center_y = message.y as f32;
+
+            let points = [
+                (center_x + size, center_y),
+                (center_x + size / 2.0, center_y - size),
+                (center_x - size / 2.0, center_y - size),
+                (center_x - size, center_y),
+                (center_x - size / 2.0, center_y + size),
+                (center_x + size / 2.0, center_y + size),
+            ];
+            match (band.antialias, &message.action) {
+                // kept from before polygons, so old hexagons look the same
+                (false, Action::DrawHexagonNormal) => fill_hexagon(band, message, &points),
+                _ => draw_polygon(
+                    band,
+                    message,
+                    &points,
+                    message.action == Action::DrawHexagonHollow,
+                ),
+            }
+        }
+        Action::DrawPolygonNormal {
+            sides,
+            rotation,
+            inner,
+        }
+        | Action::DrawPolygonHollow {
+            sides,
+            rotation,
+            inner,
+        } => {
+            let radius = message.height as f32;
+            let inner = (*inner > 0).then(|| radius * *inner as f32 / 255.0);
+
+            let points = regular_polygon(
+                (message.x as f32, message.y as f32),
+                radius,
+                *sides,
+                *rotation,
+                inner,
+            );
+            draw_polygon(
+                band,
+                message,
+                &points,
+                matches!(message.action, Action::DrawPolygonHollow { .. }),
+            );
+        }
+        Action::Stroke { points } => draw_stroke(band, message, points),
+        Action::DrawLine { x2, y2 } => draw_line(band, message, *x2, *y2),
+        Action::DrawRectangleNormal { width, height } => {
+            draw_rectangle(band, message, *width, *height, None)
+        }
+        Action::DrawRectangleHollow { width, height } => {
+            draw_rectangle(band, message, *width, *height, Some(message.height))
+        }
+        Action::DrawEllipseNormal { radius_x, radius_y } => {
+            draw_ellipse(band, message, *radius_x, *radius_y, None)
+        }
+        Action::DrawEllipseHollow { radius_x, radius_y } => {
+            draw_ellipse(band, message, *radius_x, *radius_y, Some(message.height))
+        }
+        // depends on the whole canvas, see `flood_fill`
+        Action::FloodFill => {}
+        // depends on the stamps of the server, see `stamp`
+        Action::DrawStamp { .. } => {}
+        Action::DrawSpans { spans } => {
+            for span in spans {
+                let y = span.y as usize;
+                if !band.contains_row(y) {
+                    continue;
+                }
+
+                let start_x = span.x as usize;
+                let end_x = (start_x + span.length as usize).min(band.width);
+                for x in start_x..end_x {
+                    band.set(x, y, &message.color);
+                }
+            }
+        }
+        Action::DrawText { text } => {
+            draw_text(band, message, text);
+        }
+        Action::DrawImage { width, pixels } => {
+            let width = (*width as usize).max(1);
+            let Some(last_row) = (pixels.len() / width).checked_sub(1) else {
+                return;
+            };
+
+            let start_y = message.y as usize;
+            let end_x = (message.x as usize + width).min(band.width);
+            for y in band.rows(start_y, start_y + last_row) {
+                let row = &pixels[(y - start_y) * width..];
+                for (x, color) in (message.x as usize..end_x).zip(row) {
+                    band.set(x, y, color);
+                }
+            }
+        }
+    }
+}
+
+/// Paints `width` by `height` pixels, or only an outline `thickness` pixels
+/// wide along the inside of them.
+fn draw_rectangle(
+    band: &mut Band,
+    message: &ClientMessage,
+    width: u16,
+    height: u16,
+    thickness: Option<u8>,
+) {
+    let start_x = message.x as usize;
+    let start_y = message.y as usize;
+    if start_x >= band.width || start_y >= band.height {
+        return;
+    }
+
+    let end_x = (start_x + width as usize - 1).min(band.width - 1);
+    let end_y = (start_y + height as usize - 1).min(band.height - 1);
+
+    // the edges are measured from the unclipped rectangle
+    let thickness = thickness.map(|thickness| thickness as usize);
+    let inner = thickness.map(|thickness| {
+        (
+            start_x + thickness,
+            start_y + thickness,
+            (start_x + width as usize).saturating_sub(thickness + 1),
+            (start_y + height as usize).saturating_sub(thickness + 1),
+        )
+    });
+
+    for y in band.rows(start_y, end_y) {
+        for x in start_x..=end_x {
+            if let Some((inner_start_x, inner_start_y, inner_end_x, inner_end_y)) = inner
+                && x >= inner_start_x
+                && x <= inner_end_x
+                && y >= inner_start_y
+                && y <= inner_end_y
+            {
+                continue;
+            }
+
+            band.set(x, y, &message.color);
+        }
+    }
+}
+
+/// Paints every pixel whose centre lies inside the ellipse, or only those
+/// within `thickness` pixels of its edge.
+fn draw_ellipse(
+    band: &mut Band,
+    message: &ClientMessage,
+    radius_x: u16,
+    radius_y: u16,
+    thickness: Option<u8>,
+) {
+    let center_x = message.x as f32;
+    let center_y = message.y as f32;
+    let (radius_x, radius_y) = (radius_x as f32, radius_y as f32);
+
+    let inner = thickness
+        .map(|thickness| (radius_x - thickness as f32, radius_y - thickness as f32))
+        .filter(|(inner_x, inner_y)| *inner_x > 0.0 && *inner_y > 0.0);
+
+    let (min_x, min_y, max_x, max_y) = message.bounds();
+    if max_x < 0 || max_y < 0 {
+        return;
+    }
+
+    let start_x = min_x.max(0) as usize;
+    let end_x = (max_x as usize).min(band.width - 1);
+
+    for y in band.rows(min_y.max(0) as usize, max_y as usize) {
+        let dy = y as f32 - center_y;
+
+        for x in start_x..=end_x {
+            let dx = x as f32 - center_x;
+
+            let outer = (dx / radius_x).powi(2) + (dy / radius_y).powi(2);
+            if outer > 1.0 {
+                continue;
+            }
+
+            if let Some((inner_x, inner_y)) = inner
+                && (dx / inner_x).powi(2) + (dy / inner_y).powi(2) < 1.0
+            {
+                continue;
+            }
+
+            band.set(x, y, &message.color);
+        }
+    }
+}
+
+fn draw_polygon(band: &mut Band, message: &ClientMessage, points: &[(f32, f32)], hollow: bool) {
+    match (band.antialias, hollow) {
+        (true, _) => antialias::draw_polygon(band, message, points, hollow),
+        (false, true) => outline_polygon(band, message, points),
+        (false, false) => fill_polygon(band, message, points),
+    }
+}
+
+fn fill_hexagon(band: &mut Band, message: &ClientMessage, points: &[(f32, f32); 6]) {
+    let min_y = points.iter().map(|(_, y)| *y as i32).min().unwrap();
+    let max_y = points.iter().map(|(_, y)| *y as i32).max().unwrap();
+
+    for y in min_y.max(band.start_y as i32)..=max_y.min(band.end_y as i32 - 1) {
+        let mut intersections = Vec::with_capacity(6);
+
+        for i in 0..6 {
+            let start = points[i];
+            let end = points[(i + 1) % 6];
+
+            if (start.1 <= y as f32 && end.1 > y as f32)
+                || (end.1 <= y as f32 && start.1 > y as f32)
+            {
+                let x = if start.1 == end.1 {
+                    start.0
+                } else {
+                    start.0 + (y as f32 - start.1) * (end.0 - start.0) / (end.1 - start.1)
+                };
+                intersections.push(x as i32);
+            }
+        }
+
+        intersections.sort_unstable();
+
+        for chunk in intersections.chunks(2) {
+            if chunk.len() == 2 && chunk[1] >= 0 && chunk[0] < band.width as i32 {
+                let start_x = chunk[0].max(0).min(band.width as i32 - 1);
+                let end_x = chunk[1].max(0).min(band.width as i32 - 1);
+
+                for x in start_x..=end_x {
+                    band.set(x as usize, y as usize, &message.color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{Action, ClientMessage, MAX_SPANS, RESOLUTION_HEIGHT, RESOLUTION_WIDTH, Span};
+use std::collections::BTreeMap;
+
+/// Largest width or height of a stamp, before scaling.
+pub const MAX_STAMP_SIZE: usize = 64;
+
+/// A small bitmap that [`Action::DrawStamp`] messages refer to. Pixels
+/// that are `None` leave the canvas untouched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stamp {
+    width: usize,
+    height: usize,
+    pixels: Vec<Option<[u8; 3]>>,
+}
+
+impl Stamp {
+    /// Returns `None` if `pixels` are not `width * height` row by row, or
+    /// the stamp is empty or larger than [`MAX_STAMP_SIZE`].
+    pub fn new(width: usize, height: usize, pixels: Vec<Option<[u8; 3]>>) -> Option<Self> {
+        if width == 0
+            || height == 0
+            || width > MAX_STAMP_SIZE
+            || height > MAX_STAMP_SIZE
+            || pixels.len() != width * height
+        {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+/// Resolves a [`Action::DrawStamp`] into the [`Action::DrawSpans`] messages
+/// that paint `stamp` with its top left corner at the message position,
+/// scaled up by the message `height` and multiplied with the message colour,
+/// so white stamps take on the tint and a white tint keeps their colours.
+pub fn stamp(stamp: &Stamp, message: &ClientMessage) -> Vec<ClientMessage> {
+    let scale = message.height.max(1) as usize;
+
+    // one message per colour, in a stable order
+    let mut colors: BTreeMap<[u8; 3], Vec<Span>> = BTreeMap::new();
+    for (row, pixels) in stamp.pixels.chunks(stamp.width).enumerate() {
+        let start_y = message.y as usize + row * scale;
+
+        let mut column = 0;
+        while column < pixels.len() {
+            let run = pixels[column..]
+                .iter()
+                .take_while(|pixel| **pixel == pixels[column])
+                .count();
+
+            let start_x = message.x as usize + column * scale;
+            let end_x = (start_x + run * scale).min(RESOLUTION_WIDTH);
+
+            if let Some(color) = pixels[column]
+                && start_x < end_x
+            {
+                let tinted =
+                    [0, 1, 2].map(|i| (color[i] as u16 * message.color[i] as u16 / 255) as u8);
+
+                let spans = colors.entry(tinted).or_default();
+                for y in start_y..(start_y + scale).min(RESOLUTION_HEIGHT) {
+                    spans.push(Span {
+                        x: start_x as u16,
+                        y: y as u16,
+                        length: (end_x - start_x) as u16,
+                    });
+                }
+            }
+
+            column += run;
+        }
+    }
+
+    colors
+        .into_iter()
+        .flat_map(|(color, spans)| {
+            spans
+                .chunks(MAX_SPANS)
+                .map(|spans| ClientMessage {
+                    action: Action::DrawSpans {
+                        spans: spans.to_vec(),
+                    },
+                    x: message.x,
+                    y: message.y,
+                    height: message.height,
+                    color,
+                    style: message.style,
+                    layer: message.layer,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
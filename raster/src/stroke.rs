@@ -0,0 +1,137 @@
+use crate::{ClientMessage, antialias, canvas::Band};
+
+pub(crate) fn draw_stroke(band: &mut Band, message: &ClientMessage, points: &[(i8, i8)]) {
+    let mut path = Vec::with_capacity(points.len() + 1);
+    let (mut x, mut y) = (message.x as f32, message.y as f32);
+    path.push((x, y));
+    for (dx, dy) in points {
+        x += *dx as f32;
+        y += *dy as f32;
+        path.push((x, y));
+    }
+
+    draw_path(band, message, &path);
+}
+
+pub(crate) fn draw_line(band: &mut Band, message: &ClientMessage, x2: u16, y2: u16) {
+    let path = [(message.x as f32, message.y as f32), (x2 as f32, y2 as f32)];
+
+    draw_path(band, message, &path);
+}
+
+/// Paints a polyline `height` pixels wide as the union of one capsule per
+/// segment, which gives round caps and joins. Each row is filled from the
+/// merged spans, so every pixel is painted at most once.
+fn draw_path(band: &mut Band, message: &ClientMessage, path: &[(f32, f32)]) {
+    let radius = (message.height as f32 / 2.0).max(0.5);
+    if band.antialias {
+        return antialias::draw_path(band, message, path, radius);
+    }
+
+    let (_, min_y, _, max_y) = message.bounds();
+    if max_y < 0 {
+        return;
+    }
+
+    let mut spans = Vec::with_capacity(path.len());
+    for y in band.rows(min_y.max(0) as usize, max_y as usize) {
+        spans.clear();
+
+        if path.len() == 1 {
+            spans.extend(capsule_span(path[0], path[0], radius, y as f32));
+        }
+        for segment in path.windows(2) {
+            spans.extend(capsule_span(segment[0], segment[1], radius, y as f32));
+        }
+
+        spans.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut merged: Option<(f32, f32)> = None;
+        for &(start, end) in &spans {
+            match merged {
+                Some((merged_start, merged_end)) if start <= merged_end + 1.0 => {
+                    merged = Some((merged_start, merged_end.max(end)));
+                }
+                Some(span) => {
+                    fill_span(band, y, span, &message.color);
+                    merged = Some((start, end));
+                }
+                None => merged = Some((start, end)),
+            }
+        }
+
+        if let Some(span) = merged {
+            fill_span(band, y, span, &message.color);
+        }
+    }
+}
+
+#[inline(always)]
+fn fill_span(band: &mut Band, y: usize, (start, end): (f32, f32), color: &[u8; 3]) {
+    let start = start.ceil().max(0.0);
+    let end = end.floor().min(band.width as f32 - 1.0);
+
+    if start > end {
+        return;
+    }
+
+    for x in start as usize..=end as usize {
+        band.set(x, y, color);
+    }
+}
+
+/// The part of row `y` within `radius` of the segment from `a` to `b`.
+pub(crate) fn capsule_span(
+    a: (f32, f32),
+    b: (f32, f32),
+    radius: f32,
+    y: f32,
+) -> Option<(f32, f32)> {
+    let mut span: Option<(f32, f32)> = None;
+    let mut include = |start: f32, end: f32| {
+        span = Some(match span {
+            Some((span_start, span_end)) => (span_start.min(start), span_end.max(end)),
+            None => (start, end),
+        });
+    };
+
+    // the round caps at both ends
+    for (center_x, center_y) in [a, b] {
+        let dy = y - center_y;
+        let half = radius * radius - dy * dy;
+
+        if half >= 0.0 {
+            let half = half.sqrt();
+            include(center_x - half, center_x + half);
+        }
+    }
+
+    // the rectangle swept between them
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length > 0.0 {
+        let (nx, ny) = (-dy / length * radius, dx / length * radius);
+        let corners = [
+            (a.0 + nx, a.1 + ny),
+            (b.0 + nx, b.1 + ny),
+            (b.0 - nx, b.1 - ny),
+            (a.0 - nx, a.1 - ny),
+        ];
+
+        for i in 0..4 {
+            let start = corners[i];
+            let end = corners[(i + 1) % 4];
+
+            if (start.1 <= y && end.1 >= y) || (end.1 <= y && start.1 >= y) {
+                if start.1 == end.1 {
+                    include(start.0.min(end.0), start.0.max(end.0));
+                } else {
+                    let x = start.0 + (y - start.1) * (end.0 - start.0) / (end.1 - start.1);
+                    include(x, x);
+                }
+            }
+        }
+    }
+
+    span
+}
@@ -0,0 +1,193 @@
+//! Raw WebAssembly exports for the browser client, so it paints with the
+//! exact same rasteriser as the server.
+//!
+//! The client copies bytes into the buffer returned by [`board_input`] and
+//! then calls [`board_load`] or [`board_apply`]. After each call the RGBA
+//! pixels behind [`board_pixels`] are the visible layers composited, up to
+//! date inside the rectangle returned by [`board_dirty`].
+//!
+//! Frames from the server may be compact ones, see [`CompactSession`], so
+//! the client calls [`board_connect`] for every new connection.
+
+use crate::{Canvas, ClientMessage, CompactSession, Layers};
+
+pub struct Board {
+    layers: Layers,
+    session: CompactSession,
+    rgba: Vec<u8>,
+    input: Vec<u8>,
+    dirty: [u32; 4],
+}
+
+impl Board {
+    fn refresh(&mut self, min_x: i32, min_y: i32, max_x: i32, max_y: i32) {
+        let min_x = min_x.max(0) as usize;
+        let min_y = min_y.max(0) as usize;
+        let max_x = (max_x.max(0) as usize).min(self.layers.width() - 1);
+        let max_y = (max_y.max(0) as usize).min(self.layers.height() - 1);
+
+        if min_x > max_x || min_y > max_y {
+            self.dirty = [0; 4];
+            return;
+        }
+
+        let width = self.layers.width();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let index = (y * width + x) * 4;
+                let pixel = self.layers.composite_pixel(x, y).unwrap();
+                self.rgba[index..index + 3].copy_from_slice(&pixel);
+            }
+        }
+
+        self.dirty = [
+            min_x as u32,
+            min_y as u32,
+            (max_x - min_x + 1) as u32,
+            (max_y - min_y + 1) as u32,
+        ];
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn board_new(width: usize, height: usize, layers: usize) -> *mut Board {
+    let mut board = Board {
+        layers: Layers::new(width, height, layers),
+        session: CompactSession::default(),
+        rgba: vec![0xFF; width * height * 4],
+        input: Vec::new(),
+        dirty: [0; 4],
+    };
+    board.refresh(0, 0, width as i32, height as i32);
+
+    Box::into_raw(Box::new(board))
+}
+
+/// Resizes the input buffer to `len` bytes and returns a pointer to it.
+///
+/// # Safety
+/// `board` must come from [`board_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn board_input(board: *mut Board, len: usize) -> *mut u8 {
+    let board = unsafe { &mut *board };
+    board.input.resize(len, 0);
+
+    board.input.as_mut_ptr()
+}
+
+/// Replaces `layer` with the raw RGBA history of it in the input buffer, and
+/// sets whether the board is anti-aliased like the server's.
+///
+/// # Safety
+/// `board` must come from [`board_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn board_load(board: *mut Board, layer: u8, antialias: bool) -> bool {
+    let board = unsafe { &mut *board };
+    let (width, height) = (board.layers.width(), board.layers.height());
+    let input = std::mem::take(&mut board.input);
+
+    // the bottom layer is opaque
+    let canvas = match layer {
+        0 => Canvas::from_raw(
+            width,
+            height,
+            input
+                .chunks(4)
+                .flat_map(|pixel| pixel.iter().take(3))
+                .copied()
+                .collect(),
+        )
+        .filter(|_| input.len() == width * height * 4),
+        _ => Canvas::from_rgba(width, height, &input),
+    };
+
+    board.layers.set_antialias(antialias);
+    match canvas.is_some_and(|canvas| board.layers.load(layer, canvas)) {
+        true => {
+            board.refresh(0, 0, width as i32, height as i32);
+
+            true
+        }
+        false => false,
+    }
+}
+
+/// Sets whether `layer` is shown and whether it can be painted on, like the
+/// server's.
+///
+/// # Safety
+/// `board` must come from [`board_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn board_layer(board: *mut Board, layer: u8, visible: bool, locked: bool) {
+    let board = unsafe { &mut *board };
+    let (width, height) = (board.layers.width(), board.layers.height());
+
+    if let Some(layer) = board.layers.layer_mut(layer) {
+        layer.visible = visible;
+        layer.locked = locked;
+        board.refresh(0, 0, width as i32, height as i32);
+    }
+}
+
+/// Starts over with the compact frames of a new connection.
+///
+/// # Safety
+/// `board` must come from [`board_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn board_connect(board: *mut Board) {
+    let board = unsafe { &mut *board };
+    board.session = CompactSession::default();
+}
+
+/// Decodes the input buffer as a websocket frame and paints every message
+/// in it, returning how many were painted. With `sent`, the frame is one
+/// this client sends, never a compact one, which the server sends back, so
+/// only messages that [`Layers::repaints_identically`] are painted now.
+///
+/// # Safety
+/// `board` must come from [`board_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn board_apply(board: *mut Board, sent: bool) -> u32 {
+    let board = unsafe { &mut *board };
+    let mut messages = match sent {
+        true => ClientMessage::decode_batch(&board.input),
+        false => board.session.decode(&board.input).messages,
+    };
+    if sent {
+        messages.retain(|message| board.layers.repaints_identically(message));
+    }
+
+    let mut bounds = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+    for message in &messages {
+        board.layers.rasterise(message);
+
+        let (min_x, min_y, max_x, max_y) = message.bounds();
+        bounds = (
+            bounds.0.min(min_x),
+            bounds.1.min(min_y),
+            bounds.2.max(max_x),
+            bounds.3.max(max_y),
+        );
+    }
+
+    board.refresh(bounds.0, bounds.1, bounds.2, bounds.3);
+
+    messages.len() as u32
+}
+
+/// # Safety
+/// `board` must come from [`board_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn board_pixels(board: *mut Board) -> *const u8 {
+    unsafe { (*board).rgba.as_ptr() }
+}
+
+/// Returns a pointer to `[x, y, width, height]` of the pixels changed by the
+/// last call, which is all zero if nothing changed.
+///
+/// # Safety
+/// `board` must come from [`board_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn board_dirty(board: *mut Board) -> *const u32 {
+    unsafe { (*board).dirty.as_ptr() }
+}
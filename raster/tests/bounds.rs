@@ -0,0 +1,78 @@
+//! Regressions found by the fuzz targets in `fuzz/`.
+
+mod common;
+
+use common::message;
+use draw_together_raster::{Action, Canvas, ClientMessage, rasterise};
+
+fn assert_within_bounds(canvas: &mut Canvas, message: &ClientMessage) {
+    let before = canvas.clone();
+    rasterise(canvas, message);
+
+    let (min_x, min_y, max_x, max_y) = message.bounds();
+    for (i, (old, new)) in before
+        .as_bytes()
+        .chunks(3)
+        .zip(canvas.as_bytes().chunks(3))
+        .enumerate()
+    {
+        if old != new {
+            let x = (i % canvas.width()) as i32;
+            let y = (i / canvas.width()) as i32;
+
+            assert!(
+                x >= min_x && x <= max_x && y >= min_y && y <= max_y,
+                "{message:?} wrote ({x}, {y}) outside of its bounds"
+            );
+        }
+    }
+}
+
+#[test]
+fn hollow_circle_with_tiny_radius() {
+    let mut canvas = Canvas::new(16, 16);
+
+    for radius in 1..=3 {
+        assert_within_bounds(
+            &mut canvas,
+            &message(Action::DrawCircleHollow, 8, 8, radius, [0, 0, 0]),
+        );
+    }
+}
+
+#[test]
+fn hexagon_crossing_the_top_edge() {
+    let mut canvas = Canvas::new(64, 64);
+
+    assert_within_bounds(
+        &mut canvas,
+        &message(Action::DrawHexagonNormal, 32, 0, 100, [0, 0, 0]),
+    );
+    assert_within_bounds(
+        &mut canvas,
+        &message(Action::DrawHexagonHollow, 32, 0, 100, [0, 0, 0]),
+    );
+}
+
+#[test]
+fn shapes_starting_beyond_a_small_canvas() {
+    let actions = [
+        Action::Erase,
+        Action::DrawCubeNormal,
+        Action::DrawCubeHollow,
+        Action::DrawCircleNormal,
+        Action::DrawCircleHollow,
+        Action::DrawTriangleNormal,
+        Action::DrawTriangleHollow,
+        Action::DrawHexagonNormal,
+        Action::DrawHexagonHollow,
+    ];
+
+    for action in actions {
+        let mut canvas = Canvas::new(9, 5);
+
+        assert_within_bounds(&mut canvas, &message(action.clone(), 586, 2, 73, [0, 0, 0]));
+        assert_within_bounds(&mut canvas, &message(action.clone(), 4, 297, 73, [0, 0, 0]));
+        assert_within_bounds(&mut canvas, &message(action, 1856, 900, 107, [0, 0, 0]));
+    }
+}
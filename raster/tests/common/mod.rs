@@ -0,0 +1,16 @@
+//! Fixtures shared by the integration tests.
+
+use draw_together_raster::{Action, ClientMessage, Style};
+
+/// A message with the default style on the bottom layer.
+pub fn message(action: Action, x: u16, y: u16, height: u8, color: [u8; 3]) -> ClientMessage {
+    ClientMessage {
+        action,
+        x,
+        y,
+        height,
+        color,
+        style: Style::default(),
+        layer: 0,
+    }
+}
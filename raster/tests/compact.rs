@@ -0,0 +1,254 @@
+mod common;
+
+use common::message;
+use draw_together_raster::{
+    Action, Batch, Blend, COMPACT, ClientMessage, CompactSession, DecodeError, PALETTE_SIZE, Style,
+};
+
+/// A freehand line of cubes in a few colours, like a brush produces.
+fn brush() -> Vec<ClientMessage> {
+    (0..200)
+        .map(|i| {
+            let color = [[0, 0, 0], [255, 0, 0], [0, 0, 255]][i / 70];
+            message(
+                Action::DrawCubeNormal,
+                100 + i as u16 * 2,
+                300 + (i % 7) as u16,
+                4 + (i / 50) as u8,
+                color,
+            )
+        })
+        .collect()
+}
+
+/// A freehand stroke sent in pieces that each start where the last one
+/// ended, like the browser client sends it.
+fn freehand() -> Vec<ClientMessage> {
+    let (mut x, mut y) = (400i32, 500i32);
+
+    (0..20)
+        .map(|piece| {
+            let points = (0..30)
+                .map(|i| match i % 9 {
+                    0 => (12, if piece % 2 == 0 { -9 } else { 9 }),
+                    step => ((step % 4) as i8 - 1, 2 - (step % 5) as i8),
+                })
+                .collect::<Vec<_>>();
+            let message = message(
+                Action::Stroke {
+                    points: points.clone(),
+                },
+                x as u16,
+                y as u16,
+                6,
+                [20, 20, 20],
+            );
+
+            for (dx, dy) in points {
+                x += dx as i32;
+                y += dy as i32;
+            }
+            message
+        })
+        .collect()
+}
+
+fn round_trip(frames: &[Vec<ClientMessage>]) {
+    let (mut encoder, mut decoder) = (CompactSession::default(), CompactSession::default());
+
+    for messages in frames {
+        let frame = encoder.encode(messages);
+        assert_eq!(frame[0], COMPACT);
+        assert_eq!(
+            decoder.decode(&frame),
+            Batch {
+                messages: messages.clone(),
+                errors: Vec::new(),
+            }
+        );
+    }
+}
+
+#[test]
+fn round_trips_brush_strokes() {
+    round_trip(&[brush()]);
+    round_trip(&[
+        brush()[..1].to_vec(),
+        brush()[1..90].to_vec(),
+        Vec::new(),
+        brush(),
+    ]);
+}
+
+#[test]
+fn round_trips_embedded_messages() {
+    let mut messages = brush()[..10].to_vec();
+    messages.push(message(
+        Action::DrawLine { x2: 1919, y2: 0 },
+        0,
+        999,
+        200,
+        [1, 2, 3],
+    ));
+    messages.push(ClientMessage {
+        layer: 1,
+        ..message(Action::Erase, 1919, 999, 127, [9, 9, 9])
+    });
+    messages.extend(brush()[10..20].iter().cloned());
+
+    round_trip(&[messages]);
+}
+
+#[test]
+fn round_trips_strokes() {
+    let mut messages = freehand();
+    messages.insert(
+        3,
+        message(
+            Action::Stroke {
+                points: vec![(-8, -8), (7, -8), (-8, 7), (127, -128), (0, 0)],
+            },
+            10,
+            10,
+            1,
+            [1, 2, 3],
+        ),
+    );
+    messages.insert(
+        8,
+        ClientMessage {
+            style: Style {
+                opacity: 0x80,
+                blend: Blend::Multiply,
+            },
+            ..messages[7].clone()
+        },
+    );
+    messages.insert(
+        12,
+        ClientMessage {
+            layer: 1,
+            ..messages[11].clone()
+        },
+    );
+    messages.extend(brush()[..10].iter().cloned());
+
+    round_trip(&[messages[..5].to_vec(), messages[5..].to_vec()]);
+}
+
+#[test]
+fn round_trips_more_colours_than_the_palette() {
+    let messages = (0..PALETTE_SIZE * 3)
+        .map(|i| {
+            let color = [(i % (PALETTE_SIZE + 5)) as u8, 0, (i % 3) as u8];
+            message(Action::DrawCircleNormal, i as u16, 0, 1, color)
+        })
+        .collect::<Vec<_>>();
+
+    round_trip(&[messages.clone(), messages]);
+}
+
+#[test]
+fn is_smaller_than_legacy_messages() {
+    let messages = brush();
+    let legacy = messages.iter().flat_map(|m| m.encode()).count();
+    let compact = CompactSession::default().encode(&messages).len();
+
+    assert!(compact * 2 < legacy, "{compact} bytes against {legacy}");
+}
+
+#[test]
+fn strokes_are_smaller_than_extended_messages() {
+    let messages = freehand();
+    let extended = messages.iter().flat_map(|m| m.encode()).count();
+    let compact = CompactSession::default().encode(&messages).len();
+
+    assert!(
+        compact * 3 < extended * 2,
+        "{compact} bytes against {extended}"
+    );
+}
+
+#[test]
+fn decodes_legacy_frames() {
+    let messages = brush();
+    let frame = messages.iter().flat_map(|m| m.encode()).collect::<Vec<_>>();
+
+    assert_eq!(CompactSession::default().decode(&frame).messages, messages);
+}
+
+#[test]
+fn checked_batches_reject_compact_frames() {
+    let frame = CompactSession::default().encode(&brush());
+
+    assert_eq!(
+        ClientMessage::decode_batch_checked(&frame),
+        Batch {
+            messages: Vec::new(),
+            errors: vec![DecodeError::UnknownAction],
+        }
+    );
+    assert!(ClientMessage::decode_batch(&frame).is_empty());
+}
+
+#[test]
+fn rejects_invalid_records() {
+    let cube = |x, height| message(Action::DrawCubeNormal, x, 10, height, [1, 2, 3]);
+    let mut frame = CompactSession::default().encode(&[cube(10, 4), cube(11, 4)]);
+
+    // a record with a zero height is skipped, the rest still decodes
+    frame.extend([0x01, 0x02, 0x00, 0x00, 0x01, 0x02, 0x03]);
+    frame.extend([0x31, 0x02, 0x00]);
+    // an unknown palette index
+    frame.extend([0x51, 0x02, 0x00, 0x3F]);
+    // out of the canvas
+    frame.extend([0x31, 0x7F, 0x00]);
+    let batch = CompactSession::default().decode(&frame);
+    assert_eq!(batch.messages, vec![cube(10, 4), cube(11, 4)]);
+    assert_eq!(
+        batch.errors,
+        vec![
+            DecodeError::ZeroHeight,
+            DecodeError::ZeroHeight,
+            DecodeError::InvalidValue,
+            DecodeError::OutOfBounds,
+        ]
+    );
+
+    // records that cannot be read end the frame
+    for (record, err) in [
+        (vec![0x01, 0x02], DecodeError::Truncated),
+        (vec![0x81, 0x02, 0x00, 0x04], DecodeError::ReservedFlags),
+        (vec![0x61, 0x02, 0x00, 0x04], DecodeError::ReservedFlags),
+        (vec![0x0F, 0xF0, 0x0A], DecodeError::Truncated),
+        (
+            vec![0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            DecodeError::InvalidValue,
+        ),
+        // a stroke with more points than allowed, 513, or a wide point cut
+        // short
+        (
+            vec![0x09, 0x02, 0x00, 0x04, 1, 2, 3, 0x81, 0x04],
+            DecodeError::TooLarge,
+        ),
+        (
+            vec![0x09, 0x02, 0x00, 0x04, 1, 2, 3, 0x02, 0x11, 0x88, 0x7F],
+            DecodeError::Truncated,
+        ),
+    ] {
+        let mut frame = vec![COMPACT];
+        frame.extend(record);
+        // a valid record after it is not read, unless it would complete it
+        if err != DecodeError::Truncated {
+            frame.extend(CompactSession::default().encode(&[cube(10, 4)])[1..].iter());
+        }
+
+        assert_eq!(
+            CompactSession::default().decode(&frame),
+            Batch {
+                messages: Vec::new(),
+                errors: vec![err],
+            }
+        );
+    }
+}
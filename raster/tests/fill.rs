@@ -0,0 +1,169 @@
+mod common;
+
+use common::message;
+use draw_together_raster::{Action, Canvas, Span, flood_fill, rasterise};
+
+fn fill(canvas: &mut Canvas, x: u16, y: u16, color: [u8; 3], max_area: usize) -> Option<usize> {
+    let spans = flood_fill(
+        canvas,
+        &message(Action::FloodFill, x, y, 1, color),
+        max_area,
+    )?;
+    for spans in &spans {
+        rasterise(canvas, spans);
+    }
+
+    Some(spans.len())
+}
+
+fn count(canvas: &Canvas, color: [u8; 3]) -> usize {
+    canvas
+        .as_bytes()
+        .chunks(3)
+        .filter(|pixel| *pixel == color)
+        .count()
+}
+
+#[test]
+fn fills_inside_an_outline() {
+    let mut canvas = Canvas::new(64, 64);
+    rasterise(
+        &mut canvas,
+        &message(
+            Action::DrawRectangleHollow {
+                width: 20,
+                height: 10,
+            },
+            5,
+            5,
+            1,
+            [0, 0, 0],
+        ),
+    );
+
+    fill(&mut canvas, 10, 10, [0xFF, 0, 0], usize::MAX).unwrap();
+
+    assert_eq!(count(&canvas, [0xFF, 0, 0]), 18 * 8);
+    assert_eq!(canvas.pixel(5, 5), Some([0, 0, 0]));
+    assert_eq!(canvas.pixel(4, 4), Some([0xFF, 0xFF, 0xFF]));
+}
+
+#[test]
+fn fills_around_obstacles() {
+    let mut canvas = Canvas::new(32, 32);
+    for (x, y) in [(4, 4), (20, 10), (8, 25)] {
+        rasterise(
+            &mut canvas,
+            &message(Action::DrawCircleNormal, x, y, 3, [0, 0, 0]),
+        );
+    }
+    let background = count(&canvas, [0xFF, 0xFF, 0xFF]);
+
+    fill(&mut canvas, 31, 31, [0, 0xFF, 0], usize::MAX).unwrap();
+
+    assert_eq!(count(&canvas, [0, 0xFF, 0]), background);
+    assert_eq!(count(&canvas, [0xFF, 0xFF, 0xFF]), 0);
+}
+
+#[test]
+fn rejects_fills_over_the_area_limit() {
+    let mut canvas = Canvas::new(32, 32);
+
+    assert_eq!(fill(&mut canvas, 0, 0, [0, 0, 0], 32 * 32 - 1), None);
+    assert_eq!(count(&canvas, [0, 0, 0]), 0);
+
+    assert_eq!(fill(&mut canvas, 0, 0, [0, 0, 0], 32 * 32), Some(1));
+    assert_eq!(count(&canvas, [0, 0, 0]), 32 * 32);
+}
+
+#[test]
+fn filling_with_the_same_colour_does_nothing() {
+    let canvas = Canvas::new(8, 8);
+    let spans = flood_fill(
+        &canvas,
+        &message(Action::FloodFill, 1, 1, 1, [0xFF; 3]),
+        usize::MAX,
+    );
+
+    assert_eq!(spans, Some(Vec::new()));
+}
+
+#[test]
+fn splits_large_fills_into_several_messages() {
+    // every other column is blocked, so each row is split into many spans
+    let mut canvas = Canvas::new(400, 100);
+    for x in (1..400).step_by(2) {
+        rasterise(
+            &mut canvas,
+            &message(
+                Action::DrawRectangleNormal {
+                    width: 1,
+                    height: 99,
+                },
+                x,
+                0,
+                1,
+                [0, 0, 0],
+            ),
+        );
+    }
+
+    let messages = fill(&mut canvas, 0, 0, [0, 0, 0xFF], usize::MAX).unwrap();
+
+    assert_eq!(messages, 3);
+    assert_eq!(count(&canvas, [0xFF, 0xFF, 0xFF]), 0);
+}
+
+#[test]
+fn spans_are_clipped_to_the_canvas() {
+    let mut canvas = Canvas::new(10, 10);
+    let spans = vec![
+        Span {
+            x: 8,
+            y: 1,
+            length: 100,
+        },
+        Span {
+            x: 0,
+            y: 50,
+            length: 3,
+        },
+    ];
+
+    rasterise(
+        &mut canvas,
+        &message(Action::DrawSpans { spans }, 0, 0, 1, [0, 0, 0]),
+    );
+
+    assert_eq!(count(&canvas, [0, 0, 0]), 2);
+}
+
+#[test]
+fn tells_transparent_pixels_from_opaque_ones() {
+    let opaque = |canvas: &Canvas, color: [u8; 3]| {
+        (0..16 * 16)
+            .filter(|i| {
+                let (x, y) = (i % 16, i / 16);
+                canvas.pixel(x, y) == Some(color) && canvas.alpha(x, y) == Some(0xFF)
+            })
+            .count()
+    };
+
+    let mut canvas = Canvas::transparent(16, 16);
+    rasterise(
+        &mut canvas,
+        &message(Action::DrawCubeNormal, 4, 4, 4, [0xFF; 3]),
+    );
+    let cube = opaque(&canvas, [0xFF; 3]);
+    assert!(cube > 0);
+
+    // the empty layer is white underneath, but not the same as the cube
+    fill(&mut canvas, 0, 0, [0xFF, 0, 0], usize::MAX).unwrap();
+    assert_eq!(opaque(&canvas, [0xFF; 3]), cube);
+    assert_eq!(opaque(&canvas, [0xFF, 0, 0]), 16 * 16 - cube);
+
+    // nor is filling an empty layer with white a no-op
+    let mut canvas = Canvas::transparent(16, 16);
+    fill(&mut canvas, 0, 0, [0xFF; 3], usize::MAX).unwrap();
+    assert_eq!(opaque(&canvas, [0xFF; 3]), 16 * 16);
+}
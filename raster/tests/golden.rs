@@ -0,0 +1,491 @@
+//! Rasterises every action at a few sizes and positions and compares the
+//! result against the PNGs in `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the images after an intended
+//! change, then review the diff before committing.
+
+use draw_together_raster::{Action, Blend, Canvas, ClientMessage, Style, rasterise};
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+const WIDTH: usize = 96;
+const HEIGHT: usize = 64;
+
+const ACTIONS: [(&str, Action); 9] = [
+    ("erase", Action::Erase),
+    ("cube_normal", Action::DrawCubeNormal),
+    ("cube_hollow", Action::DrawCubeHollow),
+    ("circle_normal", Action::DrawCircleNormal),
+    ("circle_hollow", Action::DrawCircleHollow),
+    ("triangle_normal", Action::DrawTriangleNormal),
+    ("triangle_hollow", Action::DrawTriangleHollow),
+    ("hexagon_normal", Action::DrawHexagonNormal),
+    ("hexagon_hollow", Action::DrawHexagonHollow),
+];
+
+const POSITIONS: [(&str, u16, u16); 7] = [
+    ("center", 48, 32),
+    ("top_left", 0, 0),
+    ("bottom_right", 95, 63),
+    ("left", 0, 32),
+    ("right", 95, 32),
+    ("top", 48, 0),
+    ("bottom", 48, 63),
+];
+
+// painted largest first so every size stays visible
+const SIZES: [(u8, [u8; 3]); 3] = [
+    (40, [0x20, 0x40, 0xC0]),
+    (12, [0xE0, 0x30, 0x30]),
+    (3, [0, 0, 0]),
+];
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn write_png(path: &PathBuf, canvas: &Canvas) {
+    let file = File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(file, canvas.width() as u32, canvas.height() as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(canvas.as_bytes()).unwrap();
+}
+
+fn read_png(path: &PathBuf) -> Vec<u8> {
+    let file = File::open(path).unwrap_or_else(|_| {
+        panic!(
+            "missing golden image {}, run with UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+    let mut reader = png::Decoder::new(BufReader::new(file)).read_info().unwrap();
+
+    let mut data = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgb);
+    assert_eq!((info.width, info.height), (WIDTH as u32, HEIGHT as u32));
+
+    data.truncate(info.buffer_size());
+    data
+}
+
+fn assert_golden(name: &str, canvas: &Canvas) {
+    let path = golden_path(name);
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        write_png(&path, canvas);
+        return;
+    }
+
+    let expected = read_png(&path);
+    if expected != canvas.as_bytes() {
+        let actual = std::env::temp_dir().join(format!("{name}.actual.png"));
+        write_png(&actual, canvas);
+
+        let differing = expected
+            .chunks(3)
+            .zip(canvas.as_bytes().chunks(3))
+            .filter(|(a, b)| a != b)
+            .count();
+        panic!(
+            "{name}: {differing} pixels differ from the golden image, actual output written to {}",
+            actual.display()
+        );
+    }
+}
+
+fn painted(action: &Action, x: u16, y: u16) -> Canvas {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+
+    // erase needs something to erase
+    if *action == Action::Erase {
+        let background = ClientMessage {
+            action: Action::DrawCubeNormal,
+            x: 0,
+            y: 0,
+            height: 127,
+            color: [0x80, 0x80, 0x80],
+            style: Style::default(),
+            layer: 0,
+        };
+        rasterise(&mut canvas, &background);
+    }
+
+    for (height, color) in SIZES {
+        let message = ClientMessage {
+            action: action.clone(),
+            x,
+            y,
+            height,
+            color,
+            style: Style::default(),
+            layer: 0,
+        };
+        rasterise(&mut canvas, &message);
+    }
+
+    canvas
+}
+
+#[test]
+fn every_action_matches_golden_images() {
+    for (action_name, action) in &ACTIONS {
+        for (position_name, x, y) in POSITIONS {
+            let canvas = painted(action, x, y);
+            assert_golden(&format!("{action_name}_{position_name}"), &canvas);
+        }
+    }
+}
+
+#[test]
+fn strokes_match_golden_images() {
+    let strokes = [
+        ("dot", 48, 32, vec![]),
+        (
+            "zigzag",
+            8,
+            40,
+            vec![(20, -30), (20, 30), (20, -30), (20, 30)],
+        ),
+        ("sharp_turn", 10, 10, vec![(70, 20), (-60, 20)]),
+        ("across_edges", 90, 60, vec![(-100, -70), (10, 80)]),
+    ];
+
+    for (name, x, y, points) in strokes {
+        let mut canvas = Canvas::new(WIDTH, HEIGHT);
+
+        for (height, color) in [
+            (12, [0x20, 0x40, 0xC0]),
+            (3, [0xE0, 0x30, 0x30]),
+            (1, [0, 0, 0]),
+        ] {
+            let message = ClientMessage {
+                action: Action::Stroke {
+                    points: points.clone(),
+                },
+                x,
+                y,
+                height,
+                color,
+                style: Style::default(),
+                layer: 0,
+            };
+            rasterise(&mut canvas, &message);
+        }
+
+        assert_golden(&format!("stroke_{name}"), &canvas);
+    }
+}
+
+#[test]
+fn sized_shapes_match_golden_images() {
+    let shapes = [
+        ("line", Action::DrawLine { x2: 90, y2: 10 }),
+        (
+            "rectangle_normal",
+            Action::DrawRectangleNormal {
+                width: 30,
+                height: 12,
+            },
+        ),
+        (
+            "rectangle_hollow",
+            Action::DrawRectangleHollow {
+                width: 30,
+                height: 12,
+            },
+        ),
+        (
+            "ellipse_normal",
+            Action::DrawEllipseNormal {
+                radius_x: 30,
+                radius_y: 12,
+            },
+        ),
+        (
+            "ellipse_hollow",
+            Action::DrawEllipseHollow {
+                radius_x: 30,
+                radius_y: 12,
+            },
+        ),
+    ];
+
+    for (shape_name, action) in &shapes {
+        for (position_name, x, y) in [
+            ("center", 48, 32),
+            ("top_left", 0, 0),
+            ("bottom_right", 95, 63),
+        ] {
+            let mut canvas = Canvas::new(WIDTH, HEIGHT);
+
+            // the outline thickness, or line width
+            for (height, color) in [(7, [0x20, 0x40, 0xC0]), (2, [0xE0, 0x30, 0x30])] {
+                let message = ClientMessage {
+                    action: action.clone(),
+                    x,
+                    y,
+                    height,
+                    color,
+                    style: Style::default(),
+                    layer: 0,
+                };
+                rasterise(&mut canvas, &message);
+            }
+
+            assert_golden(&format!("{shape_name}_{position_name}"), &canvas);
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn text_matches_golden_images() {
+    let texts = [
+        (
+            "alphabet",
+            1,
+            1,
+            1,
+            "ABCDEFGHIJKLMNOP\nQRSTUVWXYZ\nabcdefghijklmnop\nqrstuvwxyz\n0123456789\n!\"#$%&'()*+,-./:",
+        ),
+        ("scaled", 4, 10, 3, "Hi!\n:)"),
+        ("unknown_characters", 2, 20, 2, "h\u{e9}llo\n\u{2603}\t"),
+        ("across_edges", 80, 55, 2, "clipped"),
+    ];
+
+    for (name, x, y, height, text) in texts {
+        let mut canvas = Canvas::new(WIDTH, HEIGHT);
+        let message = ClientMessage {
+            action: Action::DrawText {
+                text: text.to_owned(),
+            },
+            x,
+            y,
+            height,
+            color: [0x20, 0x40, 0xC0],
+            style: Style::default(),
+            layer: 0,
+        };
+        rasterise(&mut canvas, &message);
+
+        assert_golden(&format!("text_{name}"), &canvas);
+    }
+}
+
+#[test]
+fn antialiased_shapes_match_golden_images() {
+    let shapes = [
+        ("circle_normal", Action::DrawCircleNormal),
+        ("circle_hollow", Action::DrawCircleHollow),
+        ("triangle_normal", Action::DrawTriangleNormal),
+        ("triangle_hollow", Action::DrawTriangleHollow),
+        ("hexagon_normal", Action::DrawHexagonNormal),
+        ("hexagon_hollow", Action::DrawHexagonHollow),
+        ("line", Action::DrawLine { x2: 90, y2: 10 }),
+        (
+            "stroke",
+            Action::Stroke {
+                points: vec![(20, -30), (20, 30), (-50, 10)],
+            },
+        ),
+    ];
+
+    for (name, action) in shapes {
+        for (position_name, x, y) in [POSITIONS[0], POSITIONS[1], POSITIONS[2]] {
+            let mut canvas = Canvas::new(WIDTH, HEIGHT);
+            canvas.set_antialias(true);
+
+            for (height, color) in SIZES {
+                let message = ClientMessage {
+                    action: action.clone(),
+                    x,
+                    y,
+                    height: height.min(20),
+                    color,
+                    style: Style::default(),
+                    layer: 0,
+                };
+                rasterise(&mut canvas, &message);
+            }
+
+            assert_golden(&format!("antialias_{name}_{position_name}"), &canvas);
+        }
+    }
+}
+
+#[test]
+fn polygons_match_golden_images() {
+    let polygons = [
+        ("pentagon", 5, 0, 0),
+        ("rotated_square", 4, 45, 0),
+        ("octagon", 8, 10, 0),
+        ("star", 5, 0, 100),
+        ("rotated_star", 7, 30, 160),
+    ];
+
+    for (name, sides, rotation, inner) in polygons {
+        for antialias in [false, true] {
+            let mut canvas = Canvas::new(WIDTH, HEIGHT);
+            canvas.set_antialias(antialias);
+
+            for (hollow, x, color) in [
+                (false, 24, [0x20, 0x40, 0xC0]),
+                (true, 72, [0xE0, 0x30, 0x30]),
+            ] {
+                let action = match hollow {
+                    true => Action::DrawPolygonHollow {
+                        sides,
+                        rotation,
+                        inner,
+                    },
+                    false => Action::DrawPolygonNormal {
+                        sides,
+                        rotation,
+                        inner,
+                    },
+                };
+                let message = ClientMessage {
+                    action,
+                    x,
+                    y: 32,
+                    height: 22,
+                    color,
+                    style: Style::default(),
+                    layer: 0,
+                };
+                rasterise(&mut canvas, &message);
+            }
+
+            let prefix = match antialias {
+                true => "antialias_",
+                false => "",
+            };
+            assert_golden(&format!("{prefix}polygon_{name}"), &canvas);
+        }
+    }
+}
+
+#[test]
+fn parallel_matches_sequential() {
+    let mut messages = Vec::new();
+    for (i, (_, action)) in ACTIONS.iter().enumerate() {
+        for (j, (_, x, y)) in POSITIONS.iter().enumerate() {
+            messages.push(ClientMessage {
+                action: action.clone(),
+                x: *x,
+                y: *y,
+                height: (i * 7 + j * 3) as u8 % 50 + 3,
+                color: [i as u8 * 20, j as u8 * 30, 0x55],
+                style: Style::default(),
+                layer: 0,
+            });
+        }
+    }
+    messages.push(ClientMessage {
+        action: Action::Stroke {
+            points: vec![(100, 60), (-100, 10), (50, -70)],
+        },
+        x: 0,
+        y: 0,
+        height: 9,
+        color: [0x10, 0x20, 0x30],
+        style: Style::default(),
+        layer: 0,
+    });
+    messages.push(ClientMessage {
+        action: Action::DrawEllipseHollow {
+            radius_x: 40,
+            radius_y: 25,
+        },
+        x: 40,
+        y: 30,
+        height: 4,
+        color: [0x30, 0x20, 0x10],
+        style: Style::default(),
+        layer: 0,
+    });
+    messages.push(ClientMessage {
+        action: Action::DrawText {
+            text: "parallel\nbands".to_owned(),
+        },
+        x: 5,
+        y: 40,
+        height: 2,
+        color: [0x60, 0x10, 0x90],
+        style: Style::default(),
+        layer: 0,
+    });
+    messages.push(ClientMessage {
+        action: Action::DrawImage {
+            width: 30,
+            pixels: (0..30 * 20)
+                .map(|i| [i as u8, (i / 3) as u8, 0x80])
+                .collect(),
+        },
+        x: 80,
+        y: 40,
+        height: 1,
+        color: [0; 3],
+        style: Style::default(),
+        layer: 0,
+    });
+
+    messages.push(ClientMessage {
+        action: Action::Stroke {
+            points: vec![(90, 0), (-90, 10), (90, 10)],
+        },
+        x: 2,
+        y: 35,
+        height: 12,
+        color: [0xFF, 0xE0, 0x20],
+        style: Style {
+            opacity: 0xA0,
+            blend: Blend::Multiply,
+        },
+        layer: 0,
+    });
+    messages.push(ClientMessage {
+        action: Action::DrawPolygonNormal {
+            sides: 6,
+            rotation: 15,
+            inner: 120,
+        },
+        x: 60,
+        y: 20,
+        height: 30,
+        color: [0x90, 0x30, 0x60],
+        style: Style::default(),
+        layer: 0,
+    });
+    messages.push(ClientMessage {
+        action: Action::DrawHexagonHollow,
+        x: 48,
+        y: 48,
+        height: 20,
+        color: [0x10, 0x90, 0x10],
+        style: Style {
+            opacity: 0x60,
+            blend: Blend::Normal,
+        },
+        layer: 0,
+    });
+
+    for antialias in [false, true] {
+        let mut sequential = Canvas::new(WIDTH, HEIGHT);
+        sequential.set_antialias(antialias);
+        for message in &messages {
+            rasterise(&mut sequential, message);
+        }
+
+        let mut parallel = Canvas::new(WIDTH, HEIGHT);
+        parallel.set_antialias(antialias);
+        draw_together_raster::rasterise_parallel(&mut parallel, &messages);
+
+        assert_eq!(sequential.as_bytes(), parallel.as_bytes());
+    }
+}
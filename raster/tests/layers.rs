@@ -0,0 +1,265 @@
+mod common;
+
+use common::message;
+use draw_together_raster::{Action, Blend, Canvas, ClientMessage, Layers, Style};
+
+const RED: [u8; 3] = [0xFF, 0, 0];
+const BLUE: [u8; 3] = [0, 0, 0xFF];
+const WHITE: [u8; 3] = [0xFF; 3];
+
+fn cube(x: u16, y: u16, height: u8, color: [u8; 3], layer: u8) -> ClientMessage {
+    ClientMessage {
+        layer,
+        ..message(Action::DrawCubeNormal, x, y, height, color)
+    }
+}
+
+fn erase(x: u16, y: u16, height: u8, layer: u8) -> ClientMessage {
+    ClientMessage {
+        action: Action::Erase,
+        ..cube(x, y, height, [0; 3], layer)
+    }
+}
+
+#[test]
+fn composites_from_the_bottom_up() {
+    let mut layers = Layers::new(32, 32, 3);
+    layers.rasterise(&cube(0, 0, 20, RED, 0));
+    layers.rasterise(&cube(10, 10, 20, BLUE, 1));
+
+    assert_eq!(layers.composite_pixel(5, 5), Some(RED));
+    assert_eq!(layers.composite_pixel(15, 15), Some(BLUE));
+    assert_eq!(layers.composite_pixel(25, 25), Some(BLUE));
+    assert_eq!(layers.composite_pixel(25, 2), Some(WHITE));
+
+    // the layers themselves are untouched by the ones above them
+    assert_eq!(layers.layer(0).unwrap().canvas().pixel(15, 15), Some(RED));
+    assert_eq!(layers.layer(1).unwrap().canvas().alpha(5, 5), Some(0));
+    assert_eq!(layers.layer(2).unwrap().canvas().alpha(15, 15), Some(0));
+}
+
+#[test]
+fn erasing_a_transparent_layer_reveals_the_ones_below() {
+    let mut layers = Layers::new(32, 32, 2);
+    layers.rasterise(&cube(0, 0, 20, RED, 0));
+    layers.rasterise(&cube(0, 0, 20, BLUE, 1));
+    layers.rasterise(&erase(0, 0, 4, 1));
+
+    assert_eq!(layers.composite_pixel(2, 2), Some(RED));
+    assert_eq!(layers.composite_pixel(10, 10), Some(BLUE));
+
+    layers.rasterise(&erase(0, 0, 4, 0));
+    assert_eq!(layers.composite_pixel(2, 2), Some(WHITE));
+}
+
+#[test]
+fn hidden_layers_are_not_composited() {
+    let mut layers = Layers::new(32, 32, 2);
+    layers.rasterise(&cube(0, 0, 20, RED, 0));
+    layers.rasterise(&cube(0, 0, 20, BLUE, 1));
+
+    layers.layer_mut(1).unwrap().visible = false;
+    assert_eq!(layers.composite_pixel(2, 2), Some(RED));
+
+    layers.layer_mut(0).unwrap().visible = false;
+    assert_eq!(layers.composite_pixel(2, 2), Some(WHITE));
+    assert_eq!(
+        layers.composite().as_bytes(),
+        Canvas::new(32, 32).as_bytes()
+    );
+}
+
+#[test]
+fn locked_and_missing_layers_are_not_painted() {
+    let mut layers = Layers::new(32, 32, 2);
+    layers.layer_mut(1).unwrap().locked = true;
+
+    layers.rasterise(&cube(0, 0, 20, BLUE, 1));
+    layers.rasterise(&cube(0, 0, 20, BLUE, 2));
+
+    assert_eq!(
+        layers.composite().as_bytes(),
+        Canvas::new(32, 32).as_bytes()
+    );
+}
+
+#[test]
+fn styles_composite_like_on_one_canvas() {
+    let style = Style {
+        opacity: 0x80,
+        blend: Blend::Normal,
+    };
+    let translucent = |layer| ClientMessage {
+        style,
+        ..cube(0, 0, 20, BLUE, layer)
+    };
+
+    let mut layers = Layers::new(32, 32, 2);
+    layers.rasterise(&cube(0, 0, 20, RED, 0));
+    layers.rasterise(&translucent(1));
+
+    let mut canvas = Canvas::new(32, 32);
+    draw_together_raster::rasterise(&mut canvas, &cube(0, 0, 20, RED, 0));
+    draw_together_raster::rasterise(&mut canvas, &translucent(0));
+
+    for (x, y) in [(2, 2), (10, 10)] {
+        let composited = layers.composite_pixel(x, y).unwrap();
+        let painted = canvas.pixel(x, y).unwrap();
+
+        for (a, b) in composited.iter().zip(painted) {
+            assert!(a.abs_diff(b) <= 1, "{composited:?} {painted:?}");
+        }
+    }
+}
+
+#[test]
+fn composite_matches_composite_pixel() {
+    let mut layers = Layers::new(32, 24, 3);
+    layers.rasterise(&cube(0, 0, 20, RED, 0));
+    layers.rasterise(&ClientMessage {
+        style: Style {
+            opacity: 0x80,
+            blend: Blend::Normal,
+        },
+        ..cube(6, 4, 12, BLUE, 1)
+    });
+    layers.rasterise(&cube(20, 10, 6, WHITE, 2));
+
+    for hidden in [None, Some(0), Some(2)] {
+        if let Some(hidden) = hidden {
+            layers.layer_mut(hidden).unwrap().visible = false;
+        }
+
+        let composite = layers.composite();
+        for y in 0..24 {
+            for x in 0..32 {
+                assert_eq!(composite.pixel(x, y), layers.composite_pixel(x, y));
+            }
+        }
+    }
+}
+
+#[test]
+fn crops_every_layer() {
+    let mut layers = Layers::new(32, 24, 2);
+    layers.rasterise(&cube(0, 0, 20, RED, 0));
+    layers.rasterise(&cube(10, 10, 20, BLUE, 1));
+    layers.layer_mut(1).unwrap().locked = true;
+
+    let cropped = layers.crop(8, 6, 16, 10).unwrap();
+    assert_eq!((cropped.width(), cropped.height()), (16, 10));
+    assert!(cropped.layer(1).unwrap().locked);
+    for y in 0..10 {
+        for x in 0..16 {
+            assert_eq!(
+                cropped.composite_pixel(x, y),
+                layers.composite_pixel(x + 8, y + 6)
+            );
+        }
+    }
+
+    assert!(layers.crop(17, 0, 16, 10).is_none());
+    assert!(layers.crop(0, 20, 16, 5).is_none());
+    assert!(layers.crop(usize::MAX, 0, 2, 2).is_none());
+}
+
+#[test]
+fn rgba_round_trips() {
+    let mut layers = Layers::new(16, 16, 2);
+    layers.rasterise(&ClientMessage {
+        style: Style {
+            opacity: 0x40,
+            blend: Blend::Normal,
+        },
+        ..cube(2, 2, 5, BLUE, 1)
+    });
+
+    let canvas = layers.layer(1).unwrap().canvas();
+    let loaded = Canvas::from_rgba(16, 16, &canvas.to_rgba()).unwrap();
+    assert_eq!(loaded.to_rgba(), canvas.to_rgba());
+    assert_eq!(loaded.alpha(3, 3), Some(0x40));
+
+    assert!(layers.load(1, loaded));
+    assert!(!layers.load(0, Canvas::transparent(16, 16)));
+    assert!(!layers.load(1, Canvas::transparent(8, 16)));
+    assert!(!layers.load(2, Canvas::transparent(16, 16)));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_matches_sequential() {
+    let mut messages = Vec::new();
+    for i in 0..300u16 {
+        let mut message = cube(i * 7 % 90, i * 13 % 60, (i % 20) as u8 + 1, [i as u8; 3], 0);
+        message.layer = (i % 3) as u8;
+        if i % 5 == 0 {
+            message.action = Action::Erase;
+        }
+        if i % 7 == 0 {
+            message.style = Style {
+                opacity: 0x90,
+                blend: Blend::Multiply,
+            };
+        }
+
+        messages.push(message);
+    }
+
+    let mut sequential = Layers::new(96, 64, 3);
+    for message in &messages {
+        sequential.rasterise(message);
+    }
+
+    let mut parallel = Layers::new(96, 64, 3);
+    parallel.rasterise_parallel(&messages);
+
+    for layer in 0..3 {
+        assert_eq!(
+            sequential.layer(layer).unwrap().canvas().to_rgba(),
+            parallel.layer(layer).unwrap().canvas().to_rgba()
+        );
+    }
+}
+
+#[test]
+fn senders_end_up_with_the_pixels_of_the_server() {
+    let translucent = ClientMessage {
+        style: Style {
+            opacity: 0x80,
+            blend: Blend::Normal,
+        },
+        ..message(Action::DrawCircleNormal, 16, 16, 10, BLUE)
+    };
+    let multiplied = ClientMessage {
+        style: Style {
+            opacity: 0xFF,
+            blend: Blend::Multiply,
+        },
+        ..cube(20, 4, 8, RED, 1)
+    };
+    let opaque = cube(2, 2, 6, RED, 0);
+
+    for antialias in [false, true] {
+        let mut server = Layers::new(32, 32, 2);
+        let mut client = Layers::new(32, 32, 2);
+        server.set_antialias(antialias);
+        client.set_antialias(antialias);
+
+        for message in [&translucent, &multiplied, &opaque] {
+            // the client paints what it sends, then what the server sends back
+            if client.repaints_identically(message) {
+                client.rasterise(message);
+            }
+            client.rasterise(message);
+            server.rasterise(message);
+        }
+
+        assert!(
+            client.composite().as_bytes() == server.composite().as_bytes(),
+            "antialias {antialias}"
+        );
+        assert!(!client.repaints_identically(&translucent));
+        assert!(!client.repaints_identically(&multiplied));
+        assert_eq!(client.repaints_identically(&opaque), !antialias);
+    }
+}
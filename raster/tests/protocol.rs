@@ -0,0 +1,635 @@
+mod common;
+
+use common::message;
+use draw_together_raster::{
+    Action, Batch, Blend, ClientMessage, DecodeError, MAX_IMAGE_PIXELS, MAX_LAYERS,
+    MAX_POLYGON_SIDES, MAX_SPANS, MAX_STAMP_SCALE, MAX_STROKE_POINTS, MAX_TEXT_LENGTH,
+    MAX_TEXT_SCALE, RESOLUTION_HEIGHT, RESOLUTION_WIDTH, Span, Style,
+};
+
+const ACTIONS: [Action; 9] = [
+    Action::Erase,
+    Action::DrawCubeNormal,
+    Action::DrawCubeHollow,
+    Action::DrawCircleNormal,
+    Action::DrawCircleHollow,
+    Action::DrawTriangleNormal,
+    Action::DrawTriangleHollow,
+    Action::DrawHexagonNormal,
+    Action::DrawHexagonHollow,
+];
+
+fn assert_round_trip(message: &ClientMessage) {
+    let encoded = message.encode();
+    assert_eq!(ClientMessage::decode(&encoded).as_ref(), Ok(message));
+}
+
+#[test]
+fn round_trips_every_height() {
+    for action in &ACTIONS {
+        for height in 1..=127 {
+            assert_round_trip(&message(action.clone(), 1919, 999, height, [1, 2, 3]));
+        }
+    }
+}
+
+#[test]
+fn round_trips_every_x() {
+    for action in &ACTIONS {
+        for x in 0..RESOLUTION_WIDTH as u16 {
+            assert_round_trip(&message(action.clone(), x, 0, 127, [0xFF, 0, 0xFF]));
+        }
+    }
+}
+
+#[test]
+fn round_trips_every_y() {
+    for action in &ACTIONS {
+        for y in 0..RESOLUTION_HEIGHT as u16 {
+            assert_round_trip(&message(action.clone(), 1919, y, 1, [0, 0xFF, 0]));
+        }
+    }
+}
+
+#[test]
+fn round_trips_every_color_bit() {
+    for bit in 0..24 {
+        let value = 1u32 << bit;
+        let color = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+
+        assert_round_trip(&message(Action::DrawCubeNormal, 960, 500, 64, color));
+    }
+}
+
+#[test]
+fn encode_inverts_decode_for_valid_frames() {
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+
+    for _ in 0..200_000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        let bytes: [u8; 7] = state.to_le_bytes()[..7].try_into().unwrap();
+        if let Ok(message) = ClientMessage::decode(&bytes) {
+            assert_eq!(message.encode(), bytes);
+        }
+    }
+}
+
+#[test]
+fn rejects_invalid_frames() {
+    let valid = message(Action::DrawCubeNormal, 10, 10, 10, [0; 3]).encode();
+    assert!(ClientMessage::decode(&valid).is_ok());
+
+    assert_eq!(
+        ClientMessage::decode(&valid[..6]),
+        Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+        ClientMessage::decode(&[valid.as_slice(), &[0]].concat()),
+        Err(DecodeError::TrailingBytes)
+    );
+
+    for action in 9..=14u8 {
+        let mut bytes = valid.clone();
+        bytes[0] = (action << 4) | (bytes[0] & 0xF);
+        assert_eq!(
+            ClientMessage::decode(&bytes),
+            Err(DecodeError::UnknownAction)
+        );
+    }
+    // 15 starts an extended message instead
+    let mut extended = valid.clone();
+    extended[0] |= 0xF0;
+    assert!(ClientMessage::decode(&extended).is_err());
+
+    let zero_height = message(Action::DrawCubeNormal, 10, 10, 0, [0; 3]).encode();
+    assert_eq!(
+        ClientMessage::decode(&zero_height),
+        Err(DecodeError::ZeroHeight)
+    );
+
+    for (x, y) in [(1920, 10), (2047, 10), (10, 1000), (10, 1023)] {
+        let out_of_bounds = message(Action::DrawCubeNormal, x, y, 10, [0; 3]).encode();
+        assert_eq!(
+            ClientMessage::decode(&out_of_bounds),
+            Err(DecodeError::OutOfBounds)
+        );
+    }
+}
+
+#[test]
+fn decode_batch_skips_invalid_messages() {
+    let mut frame = Vec::new();
+    frame.extend(message(Action::DrawCubeNormal, 1, 2, 3, [4, 5, 6]).encode());
+    frame.extend(message(Action::DrawCubeNormal, 1, 2, 0, [4, 5, 6]).encode());
+    frame.extend(message(Action::Erase, 7, 8, 9, [0; 3]).encode());
+    frame.extend([0x10, 0x20]);
+
+    let decoded = ClientMessage::decode_batch(&frame);
+    assert_eq!(
+        decoded,
+        vec![
+            message(Action::DrawCubeNormal, 1, 2, 3, [4, 5, 6]),
+            message(Action::Erase, 7, 8, 9, [0; 3]),
+        ]
+    );
+}
+
+#[test]
+fn decode_batch_checked_returns_errors() {
+    let mut frame = Vec::new();
+    frame.extend(message(Action::DrawCubeNormal, 1, 2, 3, [4, 5, 6]).encode());
+    frame.extend(message(Action::DrawCubeNormal, 1, 2, 0, [4, 5, 6]).encode());
+    frame.extend(message(Action::DrawCubeNormal, 1920, 2, 3, [4, 5, 6]).encode());
+    frame.extend([0x10, 0x20]);
+
+    assert_eq!(
+        ClientMessage::decode_batch_checked(&frame),
+        Batch {
+            messages: vec![message(Action::DrawCubeNormal, 1, 2, 3, [4, 5, 6])],
+            errors: vec![
+                DecodeError::ZeroHeight,
+                DecodeError::OutOfBounds,
+                DecodeError::Truncated,
+            ],
+        }
+    );
+    assert_eq!(ClientMessage::decode_batch_checked(&[]), Batch::default());
+}
+
+#[test]
+fn validate_follows_decoding() {
+    let valid = message(Action::DrawCubeNormal, 10, 10, 10, [0; 3]);
+    assert_eq!(valid.validate(), Ok(()));
+    assert_eq!(stroke(vec![(1, 1); 4]).validate(), Ok(()));
+
+    let cases = [
+        (
+            message(Action::DrawCubeNormal, 10, 10, 0, [0; 3]),
+            DecodeError::ZeroHeight,
+        ),
+        (
+            message(Action::DrawCubeNormal, 10, 10, 200, [0; 3]),
+            DecodeError::HeightTooLarge,
+        ),
+        (
+            message(Action::DrawCubeNormal, 5000, 10, 10, [0; 3]),
+            DecodeError::OutOfBounds,
+        ),
+        (
+            stroke(vec![(1, 1); MAX_STROKE_POINTS + 1]),
+            DecodeError::TooLarge,
+        ),
+        (
+            stroke(vec![(1, 1); u16::MAX as usize]),
+            DecodeError::TooLarge,
+        ),
+        (
+            ClientMessage {
+                layer: MAX_LAYERS,
+                ..valid.clone()
+            },
+            DecodeError::InvalidValue,
+        ),
+    ];
+    for (message, err) in cases {
+        assert_eq!(message.validate(), Err(err), "{message:?}");
+    }
+}
+
+fn stroke(points: Vec<(i8, i8)>) -> ClientMessage {
+    message(Action::Stroke { points }, 1919, 999, 127, [9, 8, 7])
+}
+
+#[test]
+fn round_trips_strokes() {
+    assert_round_trip(&stroke(Vec::new()));
+    assert_round_trip(&stroke(vec![(-128, 127), (127, -128), (0, 0), (-1, 1)]));
+    assert_round_trip(&stroke(
+        (0..MAX_STROKE_POINTS)
+            .map(|i| (i as u8 as i8, (i * 7) as u8 as i8))
+            .collect(),
+    ));
+}
+
+#[test]
+fn rejects_invalid_strokes() {
+    let too_long = stroke(vec![(1, 1); MAX_STROKE_POINTS + 1]).encode();
+    assert_eq!(ClientMessage::decode(&too_long), Err(DecodeError::TooLarge));
+
+    let mut odd = stroke(vec![(1, 1)]).encode();
+    odd.push(0);
+    odd[3] += 1;
+    assert_eq!(ClientMessage::decode(&odd), Err(DecodeError::InvalidLength));
+
+    let zero_width = message(Action::Stroke { points: vec![] }, 1, 1, 0, [0; 3]).encode();
+    assert_eq!(
+        ClientMessage::decode(&zero_width),
+        Err(DecodeError::ZeroHeight)
+    );
+
+    let truncated = stroke(vec![(1, 1), (2, 2)]).encode();
+    assert_eq!(
+        ClientMessage::decode(&truncated[..truncated.len() - 1]),
+        Err(DecodeError::Truncated)
+    );
+}
+
+#[test]
+fn decode_batch_mixes_legacy_and_extended_messages() {
+    let messages = vec![
+        message(Action::DrawCubeNormal, 1, 2, 3, [4, 5, 6]),
+        stroke(vec![(3, 4), (-5, 6)]),
+        message(Action::Erase, 7, 8, 9, [0; 3]),
+        stroke(Vec::new()),
+    ];
+
+    let mut frame: Vec<u8> = messages.iter().flat_map(|m| m.encode()).collect();
+    assert_eq!(ClientMessage::decode_batch(&frame), messages);
+
+    // a truncated trailing stroke is dropped without touching the rest
+    frame.extend(&stroke(vec![(1, 1)]).encode()[..9]);
+    assert_eq!(ClientMessage::decode_batch(&frame), messages);
+}
+
+#[test]
+fn round_trips_sized_shapes() {
+    for (a, b) in [(0, 0), (1, 1), (1919, 999), (1234, 567)] {
+        assert_round_trip(&message(Action::DrawLine { x2: a, y2: b }, 5, 5, 1, [0; 3]));
+    }
+
+    for (a, b) in [(1, 1), (1920, 1000), (300, 2)] {
+        let actions = [
+            Action::DrawRectangleNormal {
+                width: a,
+                height: b,
+            },
+            Action::DrawRectangleHollow {
+                width: a,
+                height: b,
+            },
+            Action::DrawEllipseNormal {
+                radius_x: a,
+                radius_y: b,
+            },
+            Action::DrawEllipseHollow {
+                radius_x: a,
+                radius_y: b,
+            },
+        ];
+
+        for action in &actions {
+            assert_round_trip(&message(action.clone(), 1919, 0, 127, [1, 2, 3]));
+        }
+    }
+}
+
+#[test]
+fn rejects_invalid_sized_shapes() {
+    let invalid = [
+        Action::DrawLine { x2: 1920, y2: 0 },
+        Action::DrawLine { x2: 0, y2: 1000 },
+        Action::DrawRectangleNormal {
+            width: 0,
+            height: 1,
+        },
+        Action::DrawRectangleHollow {
+            width: 1,
+            height: 1001,
+        },
+        Action::DrawEllipseNormal {
+            radius_x: 1,
+            radius_y: 0,
+        },
+        Action::DrawEllipseHollow {
+            radius_x: 1921,
+            radius_y: 1,
+        },
+    ];
+
+    for action in &invalid {
+        let encoded = message(action.clone(), 5, 5, 1, [0; 3]).encode();
+        assert!(ClientMessage::decode(&encoded).is_err(), "{action:?}");
+    }
+
+    let mut trailing = message(Action::DrawLine { x2: 1, y2: 1 }, 5, 5, 1, [0; 3]).encode();
+    trailing.push(0);
+    trailing[3] += 1;
+    assert!(ClientMessage::decode(&trailing).is_err());
+}
+
+fn polygon(sides: u8, rotation: u16, inner: u8, hollow: bool) -> ClientMessage {
+    let action = match hollow {
+        true => Action::DrawPolygonHollow {
+            sides,
+            rotation,
+            inner,
+        },
+        false => Action::DrawPolygonNormal {
+            sides,
+            rotation,
+            inner,
+        },
+    };
+
+    message(action, 1919, 999, 255, [1, 2, 3])
+}
+
+#[test]
+fn round_trips_polygons() {
+    for (sides, rotation, inner) in [(3, 0, 0), (5, 359, 100), (MAX_POLYGON_SIDES, 90, 255)] {
+        assert_round_trip(&polygon(sides, rotation, inner, false));
+        assert_round_trip(&polygon(sides, rotation, inner, true));
+    }
+}
+
+#[test]
+fn rejects_invalid_polygons() {
+    for (sides, rotation) in [(2, 0), (MAX_POLYGON_SIDES + 1, 0), (5, 360), (5, u16::MAX)] {
+        let encoded = polygon(sides, rotation, 0, false).encode();
+        assert!(
+            ClientMessage::decode(&encoded).is_err(),
+            "{sides} {rotation}"
+        );
+    }
+
+    let mut short = polygon(5, 0, 0, true).encode();
+    short.pop();
+    short[3] -= 1;
+    assert!(ClientMessage::decode(&short).is_err());
+}
+
+#[test]
+fn round_trips_stamps() {
+    for id in [0, 1, u16::MAX] {
+        for height in [1, MAX_STAMP_SCALE] {
+            assert_round_trip(&message(
+                Action::DrawStamp { id },
+                1919,
+                999,
+                height,
+                [1, 2, 3],
+            ));
+        }
+    }
+}
+
+#[test]
+fn rejects_invalid_stamps() {
+    let too_large = message(
+        Action::DrawStamp { id: 0 },
+        5,
+        5,
+        MAX_STAMP_SCALE + 1,
+        [0; 3],
+    );
+    assert!(ClientMessage::decode(&too_large.encode()).is_err());
+
+    let mut short = message(Action::DrawStamp { id: 0 }, 5, 5, 1, [0; 3]).encode();
+    short.pop();
+    short[3] -= 1;
+    assert!(ClientMessage::decode(&short).is_err());
+}
+
+fn spans(spans: Vec<Span>) -> ClientMessage {
+    message(Action::DrawSpans { spans }, 10, 20, 1, [1, 2, 3])
+}
+
+#[test]
+fn round_trips_flood_fills_and_spans() {
+    assert_round_trip(&message(Action::FloodFill, 1919, 999, 1, [0, 0, 0]));
+
+    assert_round_trip(&spans(Vec::new()));
+    assert_round_trip(&spans(vec![
+        Span {
+            x: 0,
+            y: 0,
+            length: 1920,
+        },
+        Span {
+            x: 1919,
+            y: 999,
+            length: 1,
+        },
+    ]));
+    assert_round_trip(&spans(vec![
+        Span {
+            x: 5,
+            y: 6,
+            length: 7
+        };
+        MAX_SPANS
+    ]));
+}
+
+#[test]
+fn rejects_invalid_spans() {
+    let invalid = [
+        Span {
+            x: 0,
+            y: 0,
+            length: 0,
+        },
+        Span {
+            x: 1,
+            y: 0,
+            length: 1920,
+        },
+        Span {
+            x: 0,
+            y: 1000,
+            length: 1,
+        },
+    ];
+
+    for span in invalid {
+        assert!(
+            ClientMessage::decode(&spans(vec![span]).encode()).is_err(),
+            "{span:?}"
+        );
+    }
+
+    let span = Span {
+        x: 1,
+        y: 1,
+        length: 1,
+    };
+    assert!(ClientMessage::decode(&spans(vec![span; MAX_SPANS + 1]).encode()).is_err());
+}
+
+fn text(text: &str, height: u8) -> ClientMessage {
+    message(
+        Action::DrawText {
+            text: text.to_owned(),
+        },
+        100,
+        200,
+        height,
+        [1, 2, 3],
+    )
+}
+
+#[test]
+fn round_trips_text() {
+    assert_round_trip(&text("a", 1));
+    assert_round_trip(&text("Hello,\nWorld!", MAX_TEXT_SCALE));
+    assert_round_trip(&text(&"\u{2603}".repeat(MAX_TEXT_LENGTH), 2));
+}
+
+#[test]
+fn rejects_invalid_text() {
+    for invalid in [
+        text("", 1),
+        text(&"a".repeat(MAX_TEXT_LENGTH + 1), 1),
+        text("a", MAX_TEXT_SCALE + 1),
+    ] {
+        assert!(
+            ClientMessage::decode(&invalid.encode()).is_err(),
+            "{invalid:?}"
+        );
+    }
+
+    let mut invalid_utf8 = text("ab", 1).encode();
+    *invalid_utf8.last_mut().unwrap() = 0xFF;
+    assert!(ClientMessage::decode(&invalid_utf8).is_err());
+}
+
+fn image(x: u16, y: u16, width: u16, pixels: usize) -> ClientMessage {
+    let pixels = (0..pixels).map(|i| [i as u8, (i >> 8) as u8, 7]).collect();
+    message(Action::DrawImage { width, pixels }, x, y, 1, [0, 0, 0])
+}
+
+#[test]
+fn round_trips_images() {
+    assert_round_trip(&image(0, 0, 1, 1));
+    assert_round_trip(&image(1910, 990, 10, 100));
+    assert_round_trip(&image(0, 0, 1920, 1920 * 10));
+    assert_round_trip(&image(5, 5, 1000, MAX_IMAGE_PIXELS));
+}
+
+#[test]
+fn rejects_invalid_images() {
+    for invalid in [
+        image(0, 0, 0, 0),
+        image(0, 0, 1, 0),
+        image(0, 0, 0, 10),
+        image(0, 0, 3, 10),
+        image(1911, 0, 10, 10),
+        image(0, 991, 1, 10),
+        image(0, 0, 1, MAX_IMAGE_PIXELS + 1),
+    ] {
+        assert!(
+            ClientMessage::decode(&invalid.encode()).is_err(),
+            "{invalid:?}"
+        );
+    }
+
+    let mut missing_width = image(0, 0, 1, 1).encode();
+    missing_width.truncate(13);
+    missing_width[3] = 9;
+    assert!(ClientMessage::decode(&missing_width).is_err());
+}
+
+fn styled(mut message: ClientMessage, opacity: u8, blend: Blend) -> ClientMessage {
+    message.style = Style { opacity, blend };
+    message
+}
+
+#[test]
+fn round_trips_styles() {
+    for action in &ACTIONS {
+        let plain = message(action.clone(), 1919, 999, 127, [1, 2, 3]);
+        assert_eq!(plain.encode().len(), 7);
+
+        for (opacity, blend) in [
+            (0, Blend::Normal),
+            (0x80, Blend::Normal),
+            (0xFF, Blend::Multiply),
+        ] {
+            let styled = styled(plain.clone(), opacity, blend);
+            assert_eq!(styled.encode()[0], 0xF1);
+            assert_round_trip(&styled);
+        }
+    }
+
+    assert_round_trip(&styled(
+        stroke(vec![(1, 2), (-3, 4)]),
+        0x40,
+        Blend::Multiply,
+    ));
+    assert_round_trip(&styled(text("highlight", 2), 0x80, Blend::Normal));
+}
+
+#[test]
+fn rejects_invalid_styles() {
+    let valid = styled(
+        message(Action::DrawCircleNormal, 10, 20, 5, [1, 2, 3]),
+        0x80,
+        Blend::Multiply,
+    )
+    .encode();
+    assert!(ClientMessage::decode(&valid).is_ok());
+
+    let mut unknown_blend = valid.clone();
+    unknown_blend[13] = 2;
+    let mut reserved_flag = valid.clone();
+    reserved_flag[0] |= 0x4;
+    let mut missing_style = valid.clone();
+    missing_style.truncate(13);
+    missing_style[3] = 9;
+    let mut trailing_data = valid.clone();
+    trailing_data.push(0);
+    trailing_data[3] += 1;
+    let mut legacy_height = valid.clone();
+    legacy_height[8] = 0x80;
+
+    for invalid in [
+        unknown_blend,
+        reserved_flag,
+        missing_style,
+        trailing_data,
+        legacy_height,
+    ] {
+        assert!(ClientMessage::decode(&invalid).is_err(), "{invalid:?}");
+    }
+}
+
+fn layered(mut message: ClientMessage, layer: u8) -> ClientMessage {
+    message.layer = layer;
+    message
+}
+
+#[test]
+fn round_trips_layers() {
+    for action in &ACTIONS {
+        let layered = layered(
+            message(action.clone(), 1919, 999, 127, [1, 2, 3]),
+            MAX_LAYERS - 1,
+        );
+        assert_eq!(layered.encode()[0], 0xF2);
+        assert_round_trip(&layered);
+
+        assert_round_trip(&styled(layered, 0x80, Blend::Multiply));
+    }
+
+    assert_round_trip(&layered(stroke(vec![(1, 2), (-3, 4)]), 1));
+    assert_round_trip(&layered(text("note", 2), 2));
+}
+
+#[test]
+fn rejects_invalid_layers() {
+    let valid = layered(message(Action::DrawCircleNormal, 10, 20, 5, [1, 2, 3]), 1).encode();
+    assert!(ClientMessage::decode(&valid).is_ok());
+
+    let mut unknown_layer = valid.clone();
+    unknown_layer[12] = MAX_LAYERS;
+    let mut missing_layer = valid.clone();
+    missing_layer.truncate(12);
+    missing_layer[3] = 8;
+
+    for invalid in [unknown_layer, missing_layer] {
+        assert!(ClientMessage::decode(&invalid).is_err(), "{invalid:?}");
+    }
+}
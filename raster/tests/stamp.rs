@@ -0,0 +1,91 @@
+mod common;
+
+use common::message;
+use draw_together_raster::{
+    Action, Canvas, ClientMessage, MAX_STAMP_SIZE, Stamp, rasterise, stamp,
+};
+
+const RED: Option<[u8; 3]> = Some([0xFF, 0, 0]);
+const WHITE: Option<[u8; 3]> = Some([0xFF, 0xFF, 0xFF]);
+
+fn stamped(bitmap: &Stamp, message: &ClientMessage) -> Canvas {
+    let mut canvas = Canvas::new(32, 32);
+    rasterise(
+        &mut canvas,
+        &common::message(Action::DrawCubeNormal, 0, 0, 127, [0, 0, 0]),
+    );
+
+    for spans in stamp(bitmap, message) {
+        assert!(matches!(spans.action, Action::DrawSpans { .. }));
+        rasterise(&mut canvas, &spans);
+    }
+
+    canvas
+}
+
+#[test]
+fn rejects_invalid_stamps() {
+    assert!(Stamp::new(0, 1, vec![]).is_none());
+    assert!(Stamp::new(2, 2, vec![RED; 3]).is_none());
+    assert!(Stamp::new(MAX_STAMP_SIZE + 1, 1, vec![RED; MAX_STAMP_SIZE + 1]).is_none());
+    assert!(Stamp::new(MAX_STAMP_SIZE, 1, vec![RED; MAX_STAMP_SIZE]).is_some());
+}
+
+#[test]
+fn leaves_transparent_pixels_untouched() {
+    let checkers = Stamp::new(2, 2, vec![RED, None, None, RED]).unwrap();
+    let canvas = stamped(
+        &checkers,
+        &message(Action::DrawStamp { id: 0 }, 4, 6, 1, [0xFF; 3]),
+    );
+
+    assert_eq!(canvas.pixel(4, 6), Some([0xFF, 0, 0]));
+    assert_eq!(canvas.pixel(5, 6), Some([0, 0, 0]));
+    assert_eq!(canvas.pixel(4, 7), Some([0, 0, 0]));
+    assert_eq!(canvas.pixel(5, 7), Some([0xFF, 0, 0]));
+}
+
+#[test]
+fn tints_and_scales() {
+    let dot = Stamp::new(2, 1, vec![WHITE, RED]).unwrap();
+    let messages = stamp(
+        &dot,
+        &message(Action::DrawStamp { id: 0 }, 1, 1, 3, [0x80, 0x40, 0xFF]),
+    );
+    assert_eq!(messages.len(), 2);
+
+    let canvas = stamped(
+        &dot,
+        &message(Action::DrawStamp { id: 0 }, 1, 1, 3, [0x80, 0x40, 0xFF]),
+    );
+    for y in 1..4 {
+        for x in 1..4 {
+            assert_eq!(canvas.pixel(x, y), Some([0x80, 0x40, 0xFF]));
+            assert_eq!(canvas.pixel(x + 3, y), Some([0x80, 0, 0]));
+        }
+    }
+    assert_eq!(canvas.pixel(7, 1), Some([0, 0, 0]));
+    assert_eq!(canvas.pixel(1, 4), Some([0, 0, 0]));
+}
+
+#[test]
+fn clips_to_the_canvas() {
+    let row = Stamp::new(4, 1, vec![WHITE; 4]).unwrap();
+    let messages = stamp(
+        &row,
+        &message(Action::DrawStamp { id: 0 }, 1918, 999, 2, [0xFF; 3]),
+    );
+
+    let Action::DrawSpans { spans } = &messages[0].action else {
+        panic!("expected spans");
+    };
+    assert_eq!(spans.len(), 1);
+    assert_eq!((spans[0].x, spans[0].y, spans[0].length), (1918, 999, 2));
+
+    for message in &messages {
+        assert_eq!(
+            ClientMessage::decode(&message.encode()).as_ref(),
+            Ok(message)
+        );
+    }
+}
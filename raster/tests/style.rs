@@ -0,0 +1,139 @@
+mod common;
+
+use common::message;
+use draw_together_raster::{Action, Blend, Canvas, ClientMessage, Style, rasterise};
+
+const WIDTH: usize = 96;
+const HEIGHT: usize = 64;
+
+fn actions() -> Vec<Action> {
+    vec![
+        Action::Erase,
+        Action::DrawCubeNormal,
+        Action::DrawCubeHollow,
+        Action::DrawCircleNormal,
+        Action::DrawCircleHollow,
+        Action::DrawTriangleNormal,
+        Action::DrawTriangleHollow,
+        Action::DrawHexagonNormal,
+        Action::DrawHexagonHollow,
+        Action::Stroke {
+            points: vec![(40, 10), (-30, 20), (30, -25)],
+        },
+        Action::DrawLine { x2: 90, y2: 60 },
+        Action::DrawRectangleHollow {
+            width: 40,
+            height: 30,
+        },
+        Action::DrawEllipseHollow {
+            radius_x: 30,
+            radius_y: 20,
+        },
+        Action::DrawText {
+            text: "Hi".to_owned(),
+        },
+    ]
+}
+
+fn styled(action: Action, style: Style) -> ClientMessage {
+    ClientMessage {
+        style,
+        ..message(action, 30, 20, 9, [0x20, 0x80, 0xFF])
+    }
+}
+
+/// A canvas with a different colour in every pixel, so blending shows up
+/// everywhere.
+fn background() -> Canvas {
+    let data = (0..WIDTH * HEIGHT)
+        .flat_map(|i| [(i % WIDTH * 2) as u8, (i / WIDTH * 3) as u8, 0xC0])
+        .collect();
+
+    Canvas::from_raw(WIDTH, HEIGHT, data).unwrap()
+}
+
+fn blended(under: u8, over: u8, opacity: u8) -> u8 {
+    ((over as u32 * opacity as u32 + under as u32 * (255 - opacity as u32) + 127) / 255) as u8
+}
+
+#[test]
+fn translucent_shapes_blend_every_pixel_once() {
+    for action in actions() {
+        let mut opaque = background();
+        rasterise(&mut opaque, &styled(action.clone(), Style::default()));
+
+        for (opacity, blend) in [(0x80, Blend::Normal), (0xC0, Blend::Multiply)] {
+            let mut translucent = background();
+            let message = styled(action.clone(), Style { opacity, blend });
+            rasterise(&mut translucent, &message);
+
+            let pixels = background()
+                .as_bytes()
+                .chunks(3)
+                .zip(opaque.as_bytes().chunks(3))
+                .zip(translucent.as_bytes().chunks(3))
+                .map(|((under, opaque), translucent)| {
+                    (under.to_vec(), opaque.to_vec(), translucent.to_vec())
+                })
+                .collect::<Vec<_>>();
+
+            for (i, (under, opaque, translucent)) in pixels.into_iter().enumerate() {
+                if under == opaque {
+                    continue;
+                }
+
+                let expected = (0..3)
+                    .map(|c| {
+                        let over = match blend {
+                            Blend::Normal => opaque[c],
+                            Blend::Multiply => {
+                                ((opaque[c] as u32 * under[c] as u32 + 127) / 255) as u8
+                            }
+                        };
+
+                        blended(under[c], over, opacity)
+                    })
+                    .collect::<Vec<_>>();
+
+                assert_eq!(translucent, expected, "{action:?} {blend:?} at pixel {i}");
+            }
+        }
+    }
+}
+
+#[test]
+fn transparent_shapes_change_nothing() {
+    for action in actions() {
+        let mut canvas = background();
+        let style = Style {
+            opacity: 0,
+            blend: Blend::Normal,
+        };
+        rasterise(&mut canvas, &styled(action.clone(), style));
+
+        assert_eq!(canvas.as_bytes(), background().as_bytes(), "{action:?}");
+    }
+}
+
+#[test]
+fn multiply_never_lightens() {
+    let mut canvas = background();
+    let style = Style {
+        opacity: 0xFF,
+        blend: Blend::Multiply,
+    };
+    rasterise(
+        &mut canvas,
+        &styled(
+            Action::DrawRectangleNormal {
+                width: WIDTH as u16,
+                height: HEIGHT as u16,
+            },
+            style,
+        ),
+    );
+
+    for (before, after) in background().as_bytes().iter().zip(canvas.as_bytes()) {
+        assert!(after <= before);
+    }
+}
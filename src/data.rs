@@ -1,132 +1,155 @@
-use std::{path::Path, sync::Arc};
+use crate::{
+    layers::LayerConfig,
+    metrics::{self, Metrics},
+    moderation::{Moderation, RateLimiter},
+    protocol::{DrawFrame, ServerControl, VERSION_EXTENDED, VERSION_LEGACY},
+    stamps::NamedStamp,
+};
+use axum::{
+    body::Bytes,
+    extract::ws::{Message, Utf8Bytes},
+};
+use raster::{
+    Action, Batch, Canvas, ClientMessage, CompactSession, Layers, RESOLUTION_HEIGHT,
+    RESOLUTION_WIDTH,
+};
+use std::{collections::HashMap, net::IpAddr, path::Path, sync::Arc};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
-    sync::RwLock,
+    sync::{RwLock, mpsc::error::TrySendError},
 };
 
-#[derive(Debug)]
-pub enum Action {
-    Erase,
-    DrawCubeNormal,
-    DrawCubeHollow,
-    DrawCircleNormal,
-    DrawCircleHollow,
-    DrawTriangleNormal,
-    DrawTriangleHollow,
-    DrawHexagonNormal,
-    DrawHexagonHollow,
+/// Batches at least this large are painted on the rayon pool.
+const PARALLEL_THRESHOLD: usize = 256;
+/// Flood fills that would change more pixels than this are dropped.
+const MAX_FLOOD_FILL_AREA: usize = 100_000;
+/// How many frames a listener may be behind before it is dropped.
+pub const LISTENER_BUFFER: usize = 64;
+
+pub struct Data {
+    pub data: Arc<RwLock<Layers>>,
+    pub layer_names: Vec<String>,
+    pub listeners: Vec<Listener>,
+    pub stamps: Vec<NamedStamp>,
+    pub moderation: Moderation,
+    /// Whether shapes are painted with smooth edges, as configured.
+    pub antialias: bool,
+    /// Whether every message broadcast is logged, as configured.
+    pub debug: bool,
+    /// The rate limiters of clients, by address, shared by all of their
+    /// connections and requests.
+    pub limiters: HashMap<IpAddr, RateLimiter>,
+    pub metrics: Metrics,
+    /// How many messages have been broadcast so far.
+    pub seq: u64,
 }
 
-const RESOLUTION: usize = RESOLUTION_WIDTH * RESOLUTION_HEIGHT;
-const RESOLUTION_HEIGHT: usize = 1000;
-const RESOLUTION_WIDTH: usize = 1920;
-
-// binary format:
-// (4b) action  | byte 1
-// (4b) height  | byte 1
-//
-// (3b) height  | byte 2
-// (5b) x       | byte 2
-//
-// (6b) x       | byte 3
-// (2b) y       | byte 3
-//
-// (8b) y       | byte 4
-//
-// (8b) color[0]| byte 5
-// (8b) color[1]| byte 6
-// (8b) color[2]| byte 7
-
-#[derive(Debug)]
-pub struct ClientMessage {
-    pub action: Action,
-
-    pub x: u16,
-    pub y: u16,
-
-    pub height: u8,
-    pub color: [u8; 3],
+/// What became of a batch, see [`Data::receive`].
+pub struct Received {
+    /// How many messages were drawn.
+    pub accepted: usize,
+    /// Whether the client may not draw at all, see [`Moderation::refuse`].
+    pub refused: bool,
+    /// Notices for what was not drawn.
+    pub notices: Vec<ServerControl>,
 }
 
-impl ClientMessage {
-    pub fn decode(data: &[u8]) -> Option<Self> {
-        if data.len() != 7 {
-            return None;
-        }
+pub struct Listener {
+    sender: tokio::sync::mpsc::Sender<Message>,
+    subscription: Arc<std::sync::RwLock<Subscription>>,
+    /// Encodes the frames of listeners that use the compact format.
+    session: CompactSession,
+    /// Whether a frame did not fit in the channel, so the listener is
+    /// dropped instead of holding up drawing.
+    lagging: bool,
+}
 
-        let action = match (data[0] >> 4) & 0xF {
-            0 => Action::Erase,
-            1 => Action::DrawCubeNormal,
-            2 => Action::DrawCubeHollow,
-            3 => Action::DrawCircleNormal,
-            4 => Action::DrawCircleHollow,
-            5 => Action::DrawTriangleNormal,
-            6 => Action::DrawTriangleHollow,
-            7 => Action::DrawHexagonNormal,
-            8 => Action::DrawHexagonHollow,
-            _ => return None,
-        };
+impl Listener {
+    pub fn is_open(&self) -> bool {
+        !self.lagging && !self.sender.is_closed()
+    }
+}
 
-        let height_high = data[0] & 0xF;
-        let height_low = (data[1] >> 5) & 0x7;
-        let height = (height_high << 3) | height_low;
-        let x_high = data[1] & 0x1F;
-        let x_low = (data[2] >> 2) & 0x3F;
-        let x = ((x_high as u16) << 6) | (x_low as u16);
-        let y_high = data[2] & 0x3;
-        let y = ((y_high as u16) << 8) | (data[3] as u16);
-        let color = [data[4], data[5], data[6]];
-
-        if height == 0 || x >= RESOLUTION_WIDTH as u16 || y >= RESOLUTION_HEIGHT as u16 {
-            return None;
-        }
+/// Which messages a listener is sent, changed by its connection as it
+/// negotiates.
+pub struct Subscription {
+    /// Only messages on this layer are sent, if set.
+    pub layer: Option<u8>,
+    /// The protocol version, see [`crate::protocol`].
+    pub version: u8,
+    /// Whether messages are sent as JSON text frames instead of binary ones.
+    pub json: bool,
+    /// Whether binary frames use the compact format, see
+    /// [`raster::CompactSession`].
+    pub compact: bool,
+    /// Only messages that can touch this inclusive `(min_x, min_y, max_x,
+    /// max_y)` rectangle are sent, if set.
+    pub region: Option<(i32, i32, i32, i32)>,
+}
 
-        Some(Self {
-            action,
-            x,
-            y,
-            height,
-            color,
-        })
+impl Subscription {
+    /// A subscription for a client that has not sent a hello yet, which is
+    /// only sent legacy messages.
+    pub fn new(layer: Option<u8>) -> Self {
+        Self {
+            layer,
+            version: VERSION_LEGACY,
+            json: false,
+            compact: false,
+            region: None,
+        }
     }
 
-    pub fn encode(&self) -> [u8; 7] {
-        let mut buf = [0; 7];
-
-        let action_value = match self.action {
-            Action::Erase => 0,
-            Action::DrawCubeNormal => 1,
-            Action::DrawCubeHollow => 2,
-            Action::DrawCircleNormal => 3,
-            Action::DrawCircleHollow => 4,
-            Action::DrawTriangleNormal => 5,
-            Action::DrawTriangleHollow => 6,
-            Action::DrawHexagonNormal => 7,
-            Action::DrawHexagonHollow => 8,
-        };
-
-        buf[0] = (action_value << 4) | ((self.height >> 3) & 0xF);
-        buf[1] = ((self.height & 0x7) << 5) | ((self.x >> 6) as u8 & 0x1F);
-        buf[2] = (((self.x & 0x3F) << 2) | ((self.y >> 8) & 0x3)) as u8;
-        buf[3] = self.y as u8;
-        buf[4..].copy_from_slice(&self.color);
+    fn accepts_all(&self) -> bool {
+        self.layer.is_none() && self.version >= VERSION_EXTENDED && self.region.is_none()
+    }
 
-        if std::env::var("DEBUG").is_ok() {
-            println!("encoded: {:?}", &self);
-        }
+    fn accepts(&self, message: &ClientMessage, encoded: &[u8]) -> bool {
+        self.layer.is_none_or(|layer| message.layer == layer)
+            && (self.version >= VERSION_EXTENDED || encoded.len() == 7)
+            && self.region.is_none_or(|(min_x, min_y, max_x, max_y)| {
+                let bounds = message.bounds();
+                bounds.0 <= max_x && bounds.2 >= min_x && bounds.1 <= max_y && bounds.3 >= min_y
+            })
+    }
+}
 
-        buf
+/// The region of a [`Subscription`] from the top left corner and size,
+/// `None` if all of them are left out.
+pub fn region(
+    x: Option<i32>,
+    y: Option<i32>,
+    w: Option<i32>,
+    h: Option<i32>,
+) -> Result<Option<(i32, i32, i32, i32)>, String> {
+    match (x, y, w, h) {
+        (None, None, None, None) => Ok(None),
+        (Some(x), Some(y), Some(w), Some(h)) if w > 0 && h > 0 => Ok(Some((
+            x,
+            y,
+            x.saturating_add(w - 1),
+            y.saturating_add(h - 1),
+        ))),
+        _ => Err("a region needs x, y and a positive w and h".to_string()),
     }
 }
 
-pub struct Data {
-    pub data: Arc<RwLock<Vec<u8>>>,
-    pub listeners: Vec<tokio::sync::mpsc::Sender<Vec<u8>>>,
+/// Where the layers above the first are saved, next to the history.
+fn layer_path(path: &str, layer: usize) -> String {
+    format!("{path}.layer{layer}")
 }
 
 impl Data {
-    pub async fn new(path: Option<String>, save: bool) -> Self {
+    pub async fn new(
+        path: Option<String>,
+        save: bool,
+        antialias: bool,
+        debug: bool,
+        stamps: Vec<NamedStamp>,
+        layers: Vec<LayerConfig>,
+        moderation: Moderation,
+    ) -> Self {
         let mut file = match path.clone() {
             Some(path) => match Path::new(&path).exists() {
                 true => Some(File::open(path).await.unwrap()),
@@ -135,371 +158,515 @@ impl Data {
             None => None,
         };
 
-        let mut data: Vec<u8> = vec![0xFF; RESOLUTION * 3];
-        if file.is_some() {
-            data.clear();
-            file.as_mut().unwrap().read_to_end(&mut data).await.unwrap();
+        let mut data = Layers::new(RESOLUTION_WIDTH, RESOLUTION_HEIGHT, layers.len());
+        if let Some(file) = &mut file {
+            let mut raw = Vec::new();
+            file.read_to_end(&mut raw).await.unwrap();
+
+            let canvas = Canvas::from_raw(RESOLUTION_WIDTH, RESOLUTION_HEIGHT, raw)
+                .expect("history does not match the canvas resolution");
+            data.load(0, canvas);
         }
 
         drop(file);
 
+        for (i, config) in layers.iter().enumerate() {
+            let layer = data.layer_mut(i as u8).unwrap();
+            layer.visible = config.visible;
+            layer.locked = config.locked;
+
+            let Some(path) = path
+                .as_ref()
+                .filter(|_| i > 0)
+                .map(|path| layer_path(path, i))
+            else {
+                continue;
+            };
+            if let Ok(raw) = tokio::fs::read(&path).await {
+                let canvas = Canvas::from_rgba(RESOLUTION_WIDTH, RESOLUTION_HEIGHT, &raw)
+                    .expect("layer does not match the canvas resolution");
+                data.load(i as u8, canvas);
+            }
+        }
+        data.set_antialias(antialias);
+
         let data = Arc::new(RwLock::new(data));
-        if let Some(path) = path {
-            if save {
-                let task_data = Arc::clone(&data);
-
-                tokio::spawn(async move {
-                    let mut file = File::options()
-                        .write(true)
-                        .truncate(true)
-                        .create(true)
-                        .open(path)
-                        .await
-                        .unwrap();
+        if let Some(path) = path
+            && save
+        {
+            let task_data = Arc::clone(&data);
 
-                    loop {
-                        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+            tokio::spawn(async move {
+                let mut file = File::options()
+                    .write(true)
+                    .truncate(true)
+                    .create(true)
+                    .open(&path)
+                    .await
+                    .unwrap();
 
-                        println!("saving data...");
+                loop {
+                    tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
 
-                        file.seek(tokio::io::SeekFrom::Start(0)).await.unwrap();
+                    println!("saving data...");
 
-                        let data = task_data.read().await;
-                        file.write_all(&data).await.unwrap();
+                    file.seek(tokio::io::SeekFrom::Start(0)).await.unwrap();
+
+                    let data = task_data.read().await;
+                    file.write_all(data.layer(0).unwrap().canvas().as_bytes())
+                        .await
+                        .unwrap();
 
-                        drop(data);
-                        file.sync_all().await.unwrap();
+                    let layers = (1..data.len())
+                        .map(|i| data.layer(i as u8).unwrap().canvas().to_rgba())
+                        .collect::<Vec<_>>();
+                    drop(data);
+                    file.sync_all().await.unwrap();
 
-                        println!("saving data... done");
+                    for (i, rgba) in layers.into_iter().enumerate() {
+                        tokio::fs::write(layer_path(&path, i + 1), rgba)
+                            .await
+                            .unwrap();
                     }
-                });
-            }
+
+                    println!("saving data... done");
+                }
+            });
         }
 
         Self {
             data,
+            layer_names: layers.into_iter().map(|layer| layer.name).collect(),
             listeners: Vec::new(),
+            stamps,
+            moderation,
+            antialias,
+            debug,
+            limiters: HashMap::new(),
+            metrics: Metrics::default(),
+            seq: 0,
         }
     }
 
-    pub fn add_listener(&mut self, listener: tokio::sync::mpsc::Sender<Vec<u8>>) {
-        self.listeners.push(listener);
+    pub fn add_listener(
+        &mut self,
+        sender: tokio::sync::mpsc::Sender<Message>,
+        subscription: Arc<std::sync::RwLock<Subscription>>,
+    ) {
+        self.sync_listeners();
+        self.listeners.push(Listener {
+            sender,
+            subscription,
+            session: CompactSession::default(),
+            lagging: false,
+        });
+    }
+
+    /// The optional features of this server, offered in the handshake.
+    pub fn features(&self) -> Vec<&'static str> {
+        let mut features = vec!["notices", "json", "compact"];
+        if self.layer_names.len() > 1 {
+            features.push("layers");
+        }
+        if !self.stamps.is_empty() {
+            features.push("stamps");
+        }
+        if self.antialias {
+            features.push("antialias");
+        }
+
+        features
     }
 
     pub fn sync_listeners(&mut self) {
-        self.listeners.retain(|listener| !listener.is_closed());
+        self.listeners.retain(|listener| listener.is_open());
     }
 
-    pub async fn write(&mut self, data: &[ClientMessage]) {
-        let self_data = Arc::clone(&self.data);
-        let mut self_data = self_data.write().await;
-
-        for message in data {
-            match message.action {
-                Action::Erase => {
-                    let height = (message.height as f64) * 1.5;
-
-                    let start_x = message.x as usize;
-                    let end_x =
-                        ((message.x + height as u16).min(RESOLUTION_WIDTH as u16 - 1)) as usize;
-                    let start_y = message.y as usize;
-                    let end_y =
-                        ((message.y + height as u16).min(RESOLUTION_HEIGHT as u16 - 1)) as usize;
-
-                    for y in start_y..=end_y {
-                        let row_start = y * RESOLUTION_WIDTH * 3;
-                        for x in start_x..=end_x {
-                            let index = row_start + x * 3;
-                            self_data[index..index + 3].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
-                        }
-                    }
-                }
-                Action::DrawCubeNormal => {
-                    let height = message.height as usize;
-
-                    let start_x = message.x as usize;
-                    let end_x =
-                        ((message.x + height as u16).min(RESOLUTION_WIDTH as u16 - 1)) as usize;
-                    let start_y = message.y as usize;
-                    let end_y =
-                        ((message.y + height as u16).min(RESOLUTION_HEIGHT as u16 - 1)) as usize;
-
-                    for y in start_y..=end_y {
-                        let row_start = y * RESOLUTION_WIDTH * 3;
-                        for x in start_x..=end_x {
-                            let index = row_start + x * 3;
-                            self_data[index..index + 3].copy_from_slice(&message.color);
-                        }
-                    }
-                }
-                Action::DrawCubeHollow => {
-                    let height = message.height as usize;
-
-                    let start_x = message.x as usize;
-                    let end_x =
-                        ((message.x + height as u16).min(RESOLUTION_WIDTH as u16 - 1)) as usize;
-                    let start_y = message.y as usize;
-                    let end_y =
-                        ((message.y + height as u16).min(RESOLUTION_HEIGHT as u16 - 1)) as usize;
-
-                    for offset in 0..2 {
-                        for x in start_x..=end_x {
-                            if (start_y + offset) < RESOLUTION_HEIGHT {
-                                let top_index = (start_y + offset) * RESOLUTION_WIDTH * 3 + x * 3;
-                                self_data[top_index..top_index + 3].copy_from_slice(&message.color);
-                            }
+    /// Draws a batch a client at `ip` sent, unless it may not draw or the
+    /// address is over its rate limit.
+    pub async fn receive(&mut self, ip: IpAddr, mut batch: Batch) -> Received {
+        let mut notices = Vec::new();
+
+        let errors = metrics::count_errors(&batch.errors);
+        if !errors.is_empty() {
+            let reasons = errors
+                .iter()
+                .map(|(err, count)| format!("{err} ({count})"))
+                .collect::<Vec<_>>();
+            println!("{ip} sent invalid messages: {}", reasons.join(", "));
+
+            for (err, count) in &errors {
+                *self.metrics.decode_errors.entry(*err).or_default() += *count as u64;
+            }
+        }
 
-                            if (end_y + offset) < RESOLUTION_HEIGHT {
-                                let bottom_index = (end_y + offset) * RESOLUTION_WIDTH * 3 + x * 3;
-                                self_data[bottom_index..bottom_index + 3]
-                                    .copy_from_slice(&message.color);
-                            }
-                        }
-                    }
+        if let Some(notice) = self.moderation.refuse(ip) {
+            self.metrics.refused += 1;
+            notices.push(notice);
 
-                    for offset in 0..2 {
-                        for y in start_y..=end_y {
-                            if (start_x + offset) < RESOLUTION_WIDTH {
-                                let left_index = y * RESOLUTION_WIDTH * 3 + (start_x + offset) * 3;
-                                self_data[left_index..left_index + 3]
-                                    .copy_from_slice(&message.color);
-                            }
+            return Received {
+                accepted: 0,
+                refused: true,
+                notices,
+            };
+        }
 
-                            if (end_x + offset) < RESOLUTION_WIDTH {
-                                let right_index = y * RESOLUTION_WIDTH * 3 + (end_x + offset) * 3;
-                                self_data[right_index..right_index + 3]
-                                    .copy_from_slice(&message.color);
-                            }
-                        }
-                    }
+        self.limiters.retain(|_, limiter| !limiter.is_idle());
+        let limiter = self
+            .limiters
+            .entry(ip)
+            .or_insert_with(|| RateLimiter::new(self.moderation.rate_limit));
+
+        let allowed = limiter.take(batch.messages.len());
+        if allowed < batch.messages.len() {
+            let dropped = batch.messages.len() - allowed;
+            self.metrics.rate_limited += dropped as u64;
+            notices.push(ServerControl::RateLimited {
+                dropped,
+                retry_after_ms: limiter.retry_after().as_millis() as u64,
+            });
+            batch.messages.truncate(allowed);
+        }
+
+        let dropped = self.write(&batch.messages).await;
+        self.metrics.dropped += dropped as u64;
+        if !errors.is_empty() || dropped > 0 {
+            notices.push(ServerControl::Rejected {
+                errors: errors
+                    .into_iter()
+                    .map(|(err, count)| (err.name(), count))
+                    .collect(),
+                dropped,
+            });
+        }
+
+        Received {
+            accepted: batch.messages.len() - dropped,
+            refused: false,
+            notices,
+        }
+    }
+
+    /// Paints and broadcasts messages, returning how many were dropped.
+    pub async fn write(&mut self, data: &[ClientMessage]) -> usize {
+        let mut dropped = 0;
+
+        // messages for locked or missing layers are dropped
+        let writable;
+        let data = {
+            let layers = self.data.read().await;
+            let is_writable = |message: &ClientMessage| {
+                layers
+                    .layer(message.layer)
+                    .is_some_and(|layer| !layer.locked)
+            };
+
+            match data.iter().all(is_writable) {
+                true => data,
+                false => {
+                    writable = data
+                        .iter()
+                        .filter(|message| is_writable(message))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    dropped += data.len() - writable.len();
+                    &writable
                 }
-                Action::DrawCircleNormal | Action::DrawCircleHollow => {
-                    let radius = message.height as usize;
-                    let is_hollow = matches!(message.action, Action::DrawCircleHollow);
-
-                    let start_x = message.x.saturating_sub(radius as u16) as usize;
-                    let end_x =
-                        ((message.x + radius as u16).min(RESOLUTION_WIDTH as u16 - 1)) as usize;
-                    let start_y = message.y.saturating_sub(radius as u16) as usize;
-                    let end_y =
-                        ((message.y + radius as u16).min(RESOLUTION_HEIGHT as u16 - 1)) as usize;
-
-                    let center_x = message.x as f32;
-                    let center_y = message.y as f32;
-                    let radius_sq = (radius * radius) as f32;
-
-                    if is_hollow {
-                        let outer_radius_sq = radius_sq;
-                        let inner_radius_sq = ((radius - 2) * (radius - 2)) as f32;
-
-                        for y in start_y..=end_y {
-                            let dy = y as f32 - center_y;
-                            let dy_sq = dy * dy;
-                            let row_start = y * RESOLUTION_WIDTH * 3;
-
-                            for x in start_x..=end_x {
-                                let dx = x as f32 - center_x;
-                                let dist_sq = dx * dx + dy_sq;
-
-                                if dist_sq <= outer_radius_sq && dist_sq >= inner_radius_sq {
-                                    let index = row_start + x * 3;
-                                    self_data[index..index + 3].copy_from_slice(&message.color);
-                                }
-                            }
-                        }
-                    } else {
-                        for y in start_y..=end_y {
-                            let dy = y as f32 - center_y;
-                            let dy_sq = dy * dy;
-                            let row_start = y * RESOLUTION_WIDTH * 3;
-
-                            for x in start_x..=end_x {
-                                let dx = x as f32 - center_x;
-                                let dist_sq = dx * dx + dy_sq;
-
-                                if dist_sq <= radius_sq {
-                                    let index = row_start + x * 3;
-                                    self_data[index..index + 3].copy_from_slice(&message.color);
-                                }
+            }
+        };
+
+        // flood fills depend on everything painted before them and stamps on
+        // the stamps of the server, so they run in order and are broadcast
+        // as the spans they painted
+        let resolved;
+        let data = if data.iter().any(resolves) {
+            let mut messages = Vec::with_capacity(data.len());
+
+            for batch in data.split_inclusive(resolves) {
+                match batch.split_last() {
+                    Some((message, batch)) if resolves(message) => {
+                        self.paint(batch).await;
+                        messages.extend_from_slice(batch);
+
+                        match self.resolve(message).await {
+                            Some(spans) => {
+                                self.paint(&spans).await;
+                                messages.extend(spans);
                             }
+                            None => dropped += 1,
                         }
                     }
-                }
-                Action::DrawTriangleNormal | Action::DrawTriangleHollow => {
-                    let height = message.height as usize;
-                    let is_hollow = matches!(message.action, Action::DrawTriangleHollow);
-
-                    let x1 = message.x as i32;
-                    let y1 = message.y as i32;
-                    let x2 = (message.x as i32) - (height as i32);
-                    let y2 = message.y as i32 + (height as i32 * 2);
-                    let x3 = message.x as i32 + height as i32;
-                    let y3 = y2;
-
-                    if is_hollow {
-                        draw_line_fast(&mut self_data, x1, y1, x2, y2, &message.color);
-                        draw_line_fast(&mut self_data, x2, y2, x3, y3, &message.color);
-                        draw_line_fast(&mut self_data, x3, y3, x1, y1, &message.color);
-                    } else {
-                        let min_x = x2.min(x3).min(x1).max(0) as usize;
-                        let max_x = x2.max(x3).max(x1).min(RESOLUTION_WIDTH as i32 - 1) as usize;
-                        let min_y = y1.min(y2).min(y3).max(0) as usize;
-                        let max_y = y1.max(y2).max(y3).min(RESOLUTION_HEIGHT as i32 - 1) as usize;
-
-                        for y in min_y..=max_y {
-                            let row_start = y * RESOLUTION_WIDTH * 3;
-                            for x in min_x..=max_x {
-                                if point_in_triangle_fast(
-                                    x as i32, y as i32, x1, y1, x2, y2, x3, y3,
-                                ) {
-                                    let index = row_start + x * 3;
-                                    self_data[index..index + 3].copy_from_slice(&message.color);
-                                }
-                            }
-                        }
+                    _ => {
+                        self.paint(batch).await;
+                        messages.extend_from_slice(batch);
                     }
                 }
-                Action::DrawHexagonNormal | Action::DrawHexagonHollow => {
-                    let is_hollow = matches!(message.action, Action::DrawHexagonHollow);
-                    let size = message.height as f32;
-                    let center_x = message.x as f32;
-                    let center_y = message.y as f32;
-
-                    let points = [
-                        (center_x + size, center_y),
-                        (center_x + size / 2.0, center_y - size),
-                        (center_x - size / 2.0, center_y - size),
-                        (center_x - size, center_y),
-                        (center_x - size / 2.0, center_y + size),
-                        (center_x + size / 2.0, center_y + size),
-                    ];
-
-                    if is_hollow {
-                        for i in 0..6 {
-                            let start = points[i];
-                            let end = points[(i + 1) % 6];
-                            draw_line_fast(
-                                &mut self_data,
-                                start.0 as i32,
-                                start.1 as i32,
-                                end.0 as i32,
-                                end.1 as i32,
-                                &message.color,
-                            );
-                        }
-                    } else {
-                        let min_y = points.iter().map(|(_, y)| *y as i32).min().unwrap();
-                        let max_y = points.iter().map(|(_, y)| *y as i32).max().unwrap();
-
-                        for y in min_y..=max_y {
-                            let mut intersections = Vec::with_capacity(6);
-
-                            for i in 0..6 {
-                                let start = points[i];
-                                let end = points[(i + 1) % 6];
-
-                                if (start.1 <= y as f32 && end.1 > y as f32)
-                                    || (end.1 <= y as f32 && start.1 > y as f32)
-                                {
-                                    let x = if start.1 == end.1 {
-                                        start.0
-                                    } else {
-                                        start.0
-                                            + (y as f32 - start.1) * (end.0 - start.0)
-                                                / (end.1 - start.1)
-                                    };
-                                    intersections.push(x as i32);
-                                }
-                            }
+            }
 
-                            intersections.sort_unstable();
+            resolved = messages;
+            &resolved
+        } else {
+            self.paint(data).await;
+            data
+        };
+
+        self.seq += data.len() as u64;
+        if self.debug {
+            for message in data {
+                println!("encoded: {message:?}");
+            }
+        }
 
-                            for chunk in intersections.chunks(2) {
-                                if chunk.len() == 2 {
-                                    let start_x = chunk[0].max(0).min(RESOLUTION_WIDTH as i32 - 1);
-                                    let end_x = chunk[1].max(0).min(RESOLUTION_WIDTH as i32 - 1);
+        if !self.listeners.is_empty() && !data.is_empty() {
+            let messages = data.iter().map(|msg| msg.encode()).collect::<Vec<_>>();
+            let encoded = Bytes::from(messages.concat());
+            // only built once a listener wants it
+            let mut json = None;
+
+            for listener in &mut self.listeners {
+                if !listener.is_open() {
+                    continue;
+                }
+
+                let frame = {
+                    let subscription = listener.subscription.read().unwrap();
+                    match (subscription.accepts_all(), subscription.json) {
+                        (_, false) if subscription.compact => {
+                            let accepted = data
+                                .iter()
+                                .zip(&messages)
+                                .filter(|(msg, encoded)| subscription.accepts(msg, encoded))
+                                .map(|(msg, _)| msg)
+                                .collect::<Vec<_>>();
+                            if accepted.is_empty() {
+                                continue;
+                            }
+
+                            Message::binary(listener.session.encode(accepted))
+                        }
+                        (true, false) => Message::Binary(encoded.clone()),
+                        (true, true) => Message::Text(
+                            json.get_or_insert_with(|| {
+                                Utf8Bytes::from(DrawFrame::new(self.seq, data).to_json())
+                            })
+                            .clone(),
+                        ),
+                        (false, as_json) => {
+                            let accepted = data
+                                .iter()
+                                .zip(&messages)
+                                .filter(|(msg, encoded)| subscription.accepts(msg, encoded))
+                                .collect::<Vec<_>>();
+                            if accepted.is_empty() {
+                                continue;
+                            }
 
-                                    for x in start_x..=end_x {
-                                        let index =
-                                            (y as usize * RESOLUTION_WIDTH + x as usize) * 3;
-                                        self_data[index..index + 3].copy_from_slice(&message.color);
-                                    }
-                                }
+                            match as_json {
+                                true => Message::text(
+                                    DrawFrame::new(self.seq, accepted.iter().map(|(msg, _)| *msg))
+                                        .to_json(),
+                                ),
+                                false => Message::binary(
+                                    accepted
+                                        .iter()
+                                        .flat_map(|(_, encoded)| encoded.iter())
+                                        .copied()
+                                        .collect::<Vec<_>>(),
+                                ),
                             }
                         }
                     }
+                };
+
+                // a listener that is behind misses frames from now on, so
+                // it is dropped, which also keeps compact sessions in step
+                if let Err(TrySendError::Full(_)) = listener.sender.try_send(frame) {
+                    listener.lagging = true;
+                    self.metrics.lagging += 1;
                 }
             }
+
+            self.sync_listeners();
         }
 
-        if !self.listeners.is_empty() {
-            let mut encoded = Vec::with_capacity(7 * data.len());
-            encoded.extend(data.iter().flat_map(|msg| msg.encode()));
+        dropped
+    }
 
-            for listener in &self.listeners {
-                if listener.is_closed() {
-                    continue;
-                }
+    async fn resolve(&self, message: &ClientMessage) -> Option<Vec<ClientMessage>> {
+        match message.action {
+            Action::FloodFill => raster::flood_fill(
+                self.data.read().await.layer(message.layer)?.canvas(),
+                message,
+                MAX_FLOOD_FILL_AREA,
+            ),
+            Action::DrawStamp { id } => {
+                let stamp = self.stamps.get(id as usize)?;
+                Some(raster::stamp(&stamp.stamp, message))
+            }
+            _ => None,
+        }
+    }
 
-                listener.send(encoded.clone()).await.unwrap();
+    async fn paint(&self, data: &[ClientMessage]) {
+        if data.len() >= PARALLEL_THRESHOLD {
+            let mut self_data = Arc::clone(&self.data).write_owned().await;
+            let messages = data.to_vec();
+
+            tokio::task::spawn_blocking(move || {
+                self_data.rasterise_parallel(&messages);
+            })
+            .await
+            .unwrap();
+        } else {
+            let mut self_data = self.data.write().await;
+
+            for message in data {
+                self_data.rasterise(message);
             }
         }
     }
 }
 
-#[inline(always)]
-fn draw_line_fast(data: &mut [u8], x1: i32, y1: i32, x2: i32, y2: i32, color: &[u8; 3]) {
-    draw_single_line(data, x1, y1, x2, y2, color);
-    draw_single_line(data, x1 + 1, y1, x2 + 1, y2, color);
-    draw_single_line(data, x1, y1 + 1, x2, y2 + 1, color);
-    draw_single_line(data, x1 + 1, y1 + 1, x2 + 1, y2 + 1, color);
+/// Whether the server turns the message into others before painting it.
+fn resolves(message: &ClientMessage) -> bool {
+    matches!(message.action, Action::FloodFill | Action::DrawStamp { .. })
 }
 
-#[inline(always)]
-fn draw_single_line(data: &mut [u8], mut x1: i32, mut y1: i32, x2: i32, y2: i32, color: &[u8; 3]) {
-    let dx = (x2 - x1).abs();
-    let dy = -(y2 - y1).abs();
-    let sx = if x1 < x2 { 1 } else { -1 };
-    let sy = if y1 < y2 { 1 } else { -1 };
-    let mut err = dx + dy;
-
-    loop {
-        if x1 >= 0 && x1 < RESOLUTION_WIDTH as i32 && y1 >= 0 && y1 < RESOLUTION_HEIGHT as i32 {
-            let index = (y1 as usize * RESOLUTION_WIDTH + x1 as usize) * 3;
-            data[index..index + 3].copy_from_slice(color);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raster::Style;
 
-        if x1 == x2 && y1 == y2 {
-            break;
+    fn message(action: Action, x: u16, y: u16, layer: u8) -> ClientMessage {
+        ClientMessage {
+            action,
+            x,
+            y,
+            height: 4,
+            color: [1, 2, 3],
+            style: Style::default(),
+            layer,
         }
+    }
+
+    fn accepts(subscription: &Subscription, message: &ClientMessage) -> bool {
+        subscription.accepts(message, &message.encode())
+    }
+
+    #[test]
+    fn clients_without_a_hello_are_sent_legacy_messages() {
+        let subscription = Subscription::new(None);
+        assert!(!subscription.accepts_all());
+
+        assert!(accepts(
+            &subscription,
+            &message(Action::DrawCubeNormal, 10, 10, 0)
+        ));
+        assert!(!accepts(
+            &subscription,
+            &message(Action::DrawLine { x2: 20, y2: 20 }, 10, 10, 0)
+        ));
+        assert!(!accepts(
+            &subscription,
+            &message(Action::DrawCubeNormal, 10, 10, 1)
+        ));
+    }
+
+    #[test]
+    fn filters_by_layer_and_version() {
+        let subscription = Subscription {
+            version: VERSION_EXTENDED,
+            ..Subscription::new(Some(1))
+        };
+        assert!(!subscription.accepts_all());
+
+        assert!(accepts(
+            &subscription,
+            &message(Action::DrawLine { x2: 20, y2: 20 }, 10, 10, 1)
+        ));
+        assert!(!accepts(
+            &subscription,
+            &message(Action::DrawCubeNormal, 10, 10, 0)
+        ));
+
+        let subscription = Subscription {
+            version: VERSION_EXTENDED,
+            ..Subscription::new(None)
+        };
+        assert!(subscription.accepts_all());
+    }
 
-        let e2 = err * 2;
-        if e2 >= dy {
-            err += dy;
-            x1 += sx;
+    #[test]
+    fn filters_by_viewport() {
+        let subscription = Subscription {
+            version: VERSION_EXTENDED,
+            region: region(Some(20), Some(20), Some(10), Some(10)).unwrap(),
+            ..Subscription::new(None)
+        };
+        assert!(!subscription.accepts_all());
+
+        for (message, expected) in [
+            (message(Action::DrawCubeNormal, 25, 25, 0), true),
+            // reaches into the corner
+            (message(Action::DrawCubeNormal, 16, 16, 0), true),
+            (message(Action::DrawCubeNormal, 15, 15, 0), false),
+            (message(Action::DrawCubeNormal, 30, 20, 0), false),
+            // circles are drawn around their position
+            (message(Action::DrawCircleNormal, 33, 25, 0), true),
+            (message(Action::DrawCircleNormal, 34, 25, 0), false),
+            (message(Action::DrawLine { x2: 40, y2: 0 }, 0, 40, 0), true),
+            (message(Action::DrawLine { x2: 40, y2: 10 }, 0, 0, 0), false),
+        ] {
+            assert_eq!(accepts(&subscription, &message), expected, "{message:?}");
         }
-        if e2 <= dx {
-            err += dx;
-            y1 += sy;
+    }
+
+    #[test]
+    fn parses_regions() {
+        assert_eq!(region(None, None, None, None), Ok(None));
+        assert_eq!(
+            region(Some(-5), Some(10), Some(20), Some(1)),
+            Ok(Some((-5, 10, 14, 10)))
+        );
+        assert_eq!(
+            region(Some(i32::MAX), Some(0), Some(10), Some(10)),
+            Ok(Some((i32::MAX, 0, i32::MAX, 9)))
+        );
+
+        for (x, y, w, h) in [
+            (Some(0), Some(0), Some(0), Some(10)),
+            (Some(0), Some(0), Some(10), Some(-1)),
+            (Some(0), None, Some(10), Some(10)),
+            (None, None, Some(10), Some(10)),
+        ] {
+            assert!(region(x, y, w, h).is_err(), "{x:?} {y:?} {w:?} {h:?}");
         }
     }
-}
 
-#[allow(clippy::too_many_arguments)]
-#[inline(always)]
-fn point_in_triangle_fast(
-    px: i32,
-    py: i32,
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32,
-    x3: i32,
-    y3: i32,
-) -> bool {
-    let edge1 = (px - x1) * (y2 - y1) - (py - y1) * (x2 - x1);
-    let edge2 = (px - x2) * (y3 - y2) - (py - y2) * (x3 - x2);
-    let edge3 = (px - x3) * (y1 - y3) - (py - y3) * (x1 - x3);
-
-    (edge1 >= 0 && edge2 >= 0 && edge3 >= 0) || (edge1 <= 0 && edge2 <= 0 && edge3 <= 0)
+    #[tokio::test]
+    async fn rate_limits_every_connection_of_an_address_together() {
+        let moderation = Moderation {
+            rate_limit: Some(10),
+            ..Moderation::default()
+        };
+        let layers = crate::layers::parse(crate::layers::DEFAULT_LAYERS).unwrap();
+        let mut data = Data::new(None, false, false, false, Vec::new(), layers, moderation).await;
+
+        let batch = || Batch {
+            messages: vec![message(Action::DrawCubeNormal, 10, 10, 0); 8],
+            errors: Vec::new(),
+        };
+        let ip = IpAddr::from([192, 0, 2, 1]);
+
+        assert_eq!(data.receive(ip, batch()).await.accepted, 8);
+        let received = data.receive(ip, batch()).await;
+        assert_eq!(received.accepted, 2);
+        assert!(matches!(
+            received.notices[..],
+            [ServerControl::RateLimited { dropped: 6, .. }]
+        ));
+
+        let other = IpAddr::from([192, 0, 2, 2]);
+        assert_eq!(data.receive(other, batch()).await.accepted, 8);
+    }
 }
@@ -0,0 +1,72 @@
+use crate::{data::Data, protocol, protocol::ServerControl};
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+};
+use raster::ClientMessage;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::Mutex;
+
+#[derive(Deserialize)]
+pub struct DrawRequest {
+    messages: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct DrawResponse {
+    /// How many messages were drawn.
+    accepted: usize,
+    /// How many messages have been broadcast, including these.
+    seq: u64,
+    /// Why messages were not drawn, like the websocket notices.
+    notices: Vec<ServerControl>,
+}
+
+/// Draws a batch of messages without a websocket, either JSON like the
+/// `draw` messages of the JSON protocol or binary like a websocket frame.
+/// Rate limits, bans and maintenance apply per address like for websockets.
+pub async fn draw(
+    State(data): State<Arc<Mutex<Data>>>,
+    ConnectInfo(who): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<DrawResponse>, (StatusCode, String)> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let batch = if content_type.starts_with("application/json") {
+        let request = serde_json::from_slice::<DrawRequest>(&body)
+            .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+        protocol::decode_json(request.messages)
+    } else if content_type.starts_with("application/octet-stream") {
+        ClientMessage::decode_batch_checked(&body)
+    } else {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "only application/json and application/octet-stream are supported".to_string(),
+        ));
+    };
+
+    let mut data = data.lock().await;
+    let received = data.receive(who.ip(), batch).await;
+
+    if received.refused {
+        return Err(match received.notices.into_iter().next() {
+            Some(ServerControl::Maintenance { message }) => {
+                (StatusCode::SERVICE_UNAVAILABLE, message)
+            }
+            _ => (StatusCode::FORBIDDEN, "banned".to_string()),
+        });
+    }
+
+    Ok(Json(DrawResponse {
+        accepted: received.accepted,
+        seq: data.seq,
+        notices: received.notices,
+    }))
+}
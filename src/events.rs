@@ -0,0 +1,57 @@
+use crate::{
+    data::{self, Data, Subscription},
+    protocol::VERSION_EXTENDED,
+};
+use axum::{
+    extract::{Query, State, ws::Message},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{Stream, stream};
+use serde::Deserialize;
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::Mutex;
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    layer: Option<u8>,
+    x: Option<i32>,
+    y: Option<i32>,
+    w: Option<i32>,
+    h: Option<i32>,
+}
+
+/// Streams what is drawn as server-sent `draw` events, with the same JSON
+/// as the `draw` messages of the JSON protocol.
+/// Optionally only messages on one layer, or that can touch the region
+/// `x`, `y`, `w`, `h`.
+pub async fn events(
+    State(data): State<Arc<Mutex<Data>>>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let region = data::region(query.x, query.y, query.w, query.h)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let subscription = Subscription {
+        version: VERSION_EXTENDED,
+        json: true,
+        region,
+        ..Subscription::new(query.layer)
+    };
+
+    let (send, recieve) = tokio::sync::mpsc::channel(data::LISTENER_BUFFER);
+    data.lock()
+        .await
+        .add_listener(send, Arc::new(std::sync::RwLock::new(subscription)));
+
+    let events = stream::unfold(recieve, |mut recieve| async move {
+        loop {
+            if let Message::Text(text) = recieve.recv().await? {
+                let event = Event::default().event("draw").data(text.as_str());
+                return Some((Ok(event), recieve));
+            }
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
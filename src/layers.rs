@@ -0,0 +1,119 @@
+use crate::data::Data;
+use axum::{Json, extract::State};
+use raster::MAX_LAYERS;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// The layers when none are configured.
+pub const DEFAULT_LAYERS: &str = "drawing";
+
+/// A layer as configured by the operator.
+pub struct LayerConfig {
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+}
+
+#[derive(Serialize)]
+pub struct LayerInfo {
+    id: u8,
+    name: String,
+    visible: bool,
+    locked: bool,
+}
+
+/// Parses layers from the bottom up, separated by commas, each a name
+/// optionally followed by `:hidden` and `:locked`.
+pub fn parse(spec: &str) -> Result<Vec<LayerConfig>, String> {
+    let mut layers = Vec::new();
+
+    for entry in spec.split(',') {
+        let mut parts = entry.trim().split(':');
+        let name = parts.next().unwrap_or_default();
+        if name.is_empty() {
+            return Err("layer names must not be empty".to_string());
+        }
+
+        let mut layer = LayerConfig {
+            name: name.to_string(),
+            visible: true,
+            locked: false,
+        };
+        for flag in parts {
+            match flag {
+                "hidden" => layer.visible = false,
+                "locked" => layer.locked = true,
+                _ => return Err(format!("unknown layer flag {flag}")),
+            }
+        }
+
+        layers.push(layer);
+    }
+
+    if layers.len() > MAX_LAYERS as usize {
+        return Err(format!("at most {MAX_LAYERS} layers are supported"));
+    }
+
+    Ok(layers)
+}
+
+/// Lists the layers from the bottom up, by id.
+pub async fn list(State(data): State<Arc<Mutex<Data>>>) -> Json<Vec<LayerInfo>> {
+    let data = data.lock().await;
+    let layers = data.data.read().await;
+
+    Json(
+        data.layer_names
+            .iter()
+            .enumerate()
+            .filter_map(|(id, name)| {
+                let layer = layers.layer(id as u8)?;
+
+                Some(LayerInfo {
+                    id: id as u8,
+                    name: name.clone(),
+                    visible: layer.visible,
+                    locked: layer.locked,
+                })
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names_and_flags() {
+        let layers = parse("background:locked, drawing ,notes:hidden:locked").unwrap();
+
+        let layers = layers
+            .iter()
+            .map(|layer| (layer.name.as_str(), layer.visible, layer.locked))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layers,
+            [
+                ("background", true, true),
+                ("drawing", true, false),
+                ("notes", false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn defaults_to_one_layer() {
+        assert_eq!(parse(DEFAULT_LAYERS).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_layers() {
+        assert!(parse("").is_err());
+        assert!(parse("drawing,,notes").is_err());
+        assert!(parse("drawing:frozen").is_err());
+        assert!(parse(&vec!["layer"; MAX_LAYERS as usize + 1].join(",")).is_err());
+        assert!(parse(&vec!["layer"; MAX_LAYERS as usize].join(",")).is_ok());
+    }
+}
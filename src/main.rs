@@ -1,23 +1,65 @@
 mod data;
+mod draw;
+mod events;
+mod layers;
+mod metrics;
+mod moderation;
+mod paste;
+mod pixels;
+mod protocol;
+mod stamps;
+mod websocket;
 
 use axum::{
     Router,
     body::{Body, Bytes},
     extract::{
-        ConnectInfo, State,
-        ws::{Message, WebSocketUpgrade},
+        ConnectInfo, DefaultBodyLimit, FromRef, Query, State,
+        ws::{CloseFrame, Message, close_code},
     },
-    http::HeaderMap,
+    http::{HeaderMap, StatusCode},
     response::Response,
-    routing::{any, get},
+    routing::{any, get, post},
 };
 use futures_util::{SinkExt, stream::StreamExt};
+use protocol::{ClientControl, ServerControl};
+use raster::{Batch, DecodeError};
+use serde::Deserialize;
 use std::{net::SocketAddr, path::Path, sync::Arc};
 use tokio::sync::Mutex;
+use websocket::WebSocketUpgrade;
 
 const INDEX_HTML: &str = include_str!("../static/index.html");
+#[cfg(raster_wasm)]
+const RASTER_WASM: &[u8] = include_bytes!("../static/raster.wasm");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+#[derive(Deserialize)]
+struct LayerQuery {
+    /// A single layer instead of all of them composited.
+    layer: Option<u8>,
+}
+
+/// What the handlers share. The configuration is kept out of [`data::Data`],
+/// so reading it does not wait on drawing.
+#[derive(Clone)]
+struct AppState {
+    data: Arc<Mutex<data::Data>>,
+    compression: Option<websocket::Compression>,
+}
+
+impl FromRef<AppState> for Arc<Mutex<data::Data>> {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.data)
+    }
+}
+
+impl FromRef<AppState> for Option<websocket::Compression> {
+    fn from_ref(state: &AppState) -> Self {
+        state.compression
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let nosave = std::env::args().nth(1) == Some("--nosave".to_string());
@@ -25,6 +67,54 @@ async fn main() {
         .unwrap_or("3000".to_string())
         .parse::<u16>()
         .expect("invalid port, 0-65535");
+    let antialias = std::env::var("ANTIALIAS").is_ok_and(|value| value == "1" || value == "true");
+    let debug = std::env::var("DEBUG").is_ok();
+    let stamps = stamps::load(Path::new(
+        &std::env::var("STAMPS").unwrap_or("stamps".to_string()),
+    ));
+    let stamp_count = stamps.len();
+    let layers =
+        layers::parse(&std::env::var("LAYERS").unwrap_or(layers::DEFAULT_LAYERS.to_string()))
+            .expect("invalid layers");
+    let moderation = moderation::Moderation {
+        banned: moderation::parse_banned(&std::env::var("BANNED").unwrap_or_default())
+            .expect("invalid banned addresses"),
+        maintenance: std::env::var("MAINTENANCE").ok(),
+        rate_limit: std::env::var("RATE_LIMIT")
+            .ok()
+            .map(|limit| {
+                limit
+                    .parse::<usize>()
+                    .expect("invalid rate limit, 1 or more")
+            })
+            .filter(|limit| *limit > 0),
+    };
+    let maintenance = moderation.maintenance.is_some();
+    let compression = match std::env::var("COMPRESSION")
+        .ok()
+        .map(|level| {
+            level
+                .parse::<u32>()
+                .ok()
+                .filter(|level| *level <= 9)
+                .expect("invalid compression level, 0-9")
+        })
+        // off unless configured, as few real clients have been tried with it
+        .unwrap_or(0)
+    {
+        0 => None,
+        level => Some(websocket::Compression {
+            level,
+            threshold: std::env::var("COMPRESSION_THRESHOLD")
+                .ok()
+                .map(|threshold| {
+                    threshold
+                        .parse::<usize>()
+                        .expect("invalid compression threshold")
+                })
+                .unwrap_or(websocket::DEFAULT_THRESHOLD),
+        }),
+    };
 
     let data = Arc::new(Mutex::new(
         data::Data::new(
@@ -36,6 +126,11 @@ async fn main() {
                 },
             },
             !nosave,
+            antialias,
+            debug,
+            stamps,
+            layers,
+            moderation,
         )
         .await,
     ));
@@ -43,20 +138,73 @@ async fn main() {
     let app = Router::new()
         .route(
             "/history_2.raw",
-            get(|state: State<Arc<Mutex<data::Data>>>| async move {
-                let data = state.lock().await;
-
-                let mut headers = HeaderMap::new();
+            get(
+                |state: State<Arc<Mutex<data::Data>>>, Query(query): Query<LayerQuery>| async move {
+                    let data = Arc::clone(&state.lock().await.data);
+
+                    let mut headers = HeaderMap::new();
+
+                    headers.insert("Content-Type", "robert/history-2".parse().unwrap());
+
+                    let layers = data.read().await;
+                    headers.insert(
+                        "Antialias",
+                        (layers.antialias() as u8).to_string().parse().unwrap(),
+                    );
+
+                    // copied, so drawing is not held up while they are encoded;
+                    // single layers are RGBA, so they can be composited again
+                    let body = match query.layer {
+                        Some(layer) => {
+                            let Some(canvas) =
+                                layers.layer(layer).map(|layer| layer.canvas().clone())
+                            else {
+                                return Err(StatusCode::NOT_FOUND);
+                            };
+                            drop(layers);
+
+                            Body::from(canvas.to_rgba())
+                        }
+                        None => {
+                            let snapshot = layers.clone();
+                            drop(layers);
+
+                            Body::from(snapshot.composite().into_bytes())
+                        }
+                    };
+
+                    Ok((headers, body))
+                },
+            ),
+        )
+        .route("/ws", any(handle_ws))
+        .route(
+            "/api/paste",
+            post(paste::paste).layer(DefaultBodyLimit::max(paste::MAX_UPLOAD_BYTES)),
+        )
+        .route("/api/draw", post(draw::draw))
+        .route("/api/events", get(events::events))
+        .route("/api/pixel", get(pixels::pixel))
+        .route("/api/region", get(pixels::region))
+        .route("/api/stamps", get(stamps::list))
+        .route("/api/layers", get(layers::list))
+        .route("/metrics", get(metrics::metrics))
+        .route(
+            "/raster.wasm",
+            get(|| async {
+                #[cfg(raster_wasm)]
+                {
+                    let mut headers = HeaderMap::new();
 
-                headers.insert("Content-Type", "robert/history-2".parse().unwrap());
+                    headers.insert("Content-Type", "application/wasm".parse().unwrap());
 
-                let data = data.data.read().await;
-                let body = Body::from(data.clone());
+                    (StatusCode::OK, headers, Body::from(RASTER_WASM))
+                }
 
-                (headers, body)
+                #[cfg(not(raster_wasm))]
+                (StatusCode::NOT_FOUND, HeaderMap::new(), Body::empty())
             }),
         )
-        .route("/ws", any(handle_ws))
         .route(
             "/",
             get(|| async {
@@ -67,7 +215,7 @@ async fn main() {
                 (headers, Body::from(INDEX_HTML))
             }),
         )
-        .with_state(data);
+        .with_state(AppState { data, compression });
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
         .await
@@ -83,6 +231,21 @@ async fn main() {
     } else {
         println!("saving history to history_2.raw");
     }
+    if antialias {
+        println!("anti-aliasing shapes");
+    }
+    if stamp_count > 0 {
+        println!("loaded {stamp_count} stamps");
+    }
+    if maintenance {
+        println!("in maintenance, not drawing");
+    }
+    if let Some(compression) = compression {
+        println!(
+            "compressing websocket frames of {} bytes or more at level {}",
+            compression.threshold, compression.level
+        );
+    }
 
     axum::serve(
         listener,
@@ -96,6 +259,7 @@ async fn handle_ws(
     ws: WebSocketUpgrade,
     data: State<Arc<Mutex<data::Data>>>,
     ConnectInfo(who): ConnectInfo<SocketAddr>,
+    Query(query): Query<LayerQuery>,
 ) -> Response {
     println!("{who} connected to ws");
 
@@ -105,8 +269,17 @@ async fn handle_ws(
         let (sender, mut reciever) = socket.split();
         let sender = Arc::new(Mutex::new(sender));
 
+        let subscription = Arc::new(std::sync::RwLock::new(data::Subscription::new(query.layer)));
+
         let writer_data = Arc::clone(&data);
+        let writer_sender = Arc::clone(&sender);
+        let writer_subscription = Arc::clone(&subscription);
         let writer = tokio::spawn(async move {
+            // whether the client asked for notices in its hello
+            let mut notices = false;
+            // the state compact frames from this client are relative to
+            let mut session = raster::CompactSession::default();
+
             loop {
                 let ws_data = reciever.next().await;
                 if ws_data.is_none() {
@@ -118,41 +291,121 @@ async fn handle_ws(
                     break;
                 }
 
-                let ws_data = ws_data.unwrap().into_data();
-
-                let mut parsed = Vec::with_capacity(ws_data.len() / 7);
-                for chunk in ws_data.chunks(7) {
-                    let data = data::ClientMessage::decode(chunk);
-
-                    if data.is_none() {
-                        continue;
+                // drawing messages are binary or JSON, control messages JSON
+                let batch = match ws_data.unwrap() {
+                    Message::Binary(ws_data) => session.decode(&ws_data),
+                    Message::Text(text) => match serde_json::from_str::<ClientControl>(&text) {
+                        Ok(ClientControl::Draw { messages }) => protocol::decode_json(messages),
+                        Ok(ClientControl::Viewport { x, y, w, h }) => {
+                            match data::region(x, y, w, h) {
+                                Ok(region) => {
+                                    writer_subscription.write().unwrap().region = region;
+                                    continue;
+                                }
+                                Err(_) => Batch {
+                                    errors: vec![DecodeError::InvalidValue],
+                                    ..Batch::default()
+                                },
+                            }
+                        }
+                        Ok(ClientControl::Hello { versions, features }) => {
+                            let (reply, refused) = {
+                                let data = writer_data.lock().await;
+                                let reply = ServerControl::welcome(
+                                    &versions,
+                                    &features,
+                                    &data.features(),
+                                    data.seq,
+                                );
+
+                                (reply, data.moderation.refuse(who.ip()))
+                            };
+
+                            let mut sender = writer_sender.lock().await;
+                            sender
+                                .send(Message::text(reply.to_json()))
+                                .await
+                                .unwrap_or_default();
+
+                            match reply {
+                                ServerControl::Welcome {
+                                    version, features, ..
+                                } => {
+                                    {
+                                        let mut subscription = writer_subscription.write().unwrap();
+                                        subscription.version = version;
+                                        subscription.json = features.contains(&"json");
+                                        subscription.compact = features.contains(&"compact");
+                                    }
+
+                                    // tell clients up front when they cannot draw
+                                    notices = features.contains(&"notices");
+                                    if let Some(refused) = refused.filter(|_| notices) {
+                                        sender
+                                            .send(Message::text(refused.to_json()))
+                                            .await
+                                            .unwrap_or_default();
+                                    }
+                                }
+                                ServerControl::Unsupported { .. } => {
+                                    sender.send(Message::Close(None)).await.unwrap_or_default();
+                                    break;
+                                }
+                                _ => {}
+                            }
+
+                            continue;
+                        }
+                        Err(_) => Batch {
+                            errors: vec![DecodeError::InvalidValue],
+                            ..Batch::default()
+                        },
+                    },
+                    _ => continue,
+                };
+
+                let received = writer_data.lock().await.receive(who.ip(), batch).await;
+
+                if notices {
+                    let mut sender = writer_sender.lock().await;
+                    for reply in received.notices {
+                        sender
+                            .send(Message::text(reply.to_json()))
+                            .await
+                            .unwrap_or_default();
                     }
-
-                    parsed.push(data.unwrap());
                 }
-
-                writer_data.lock().await.write(&parsed).await;
             }
         });
 
         let (send, mut recieve): (
-            tokio::sync::mpsc::Sender<Vec<u8>>,
-            tokio::sync::mpsc::Receiver<Vec<u8>>,
-        ) = tokio::sync::mpsc::channel(7);
-        data.lock().await.add_listener(send);
+            tokio::sync::mpsc::Sender<Message>,
+            tokio::sync::mpsc::Receiver<Message>,
+        ) = tokio::sync::mpsc::channel(data::LISTENER_BUFFER);
+        data.lock().await.add_listener(send, subscription);
 
         let reader_sender = Arc::clone(&sender);
         let reader = tokio::spawn(async move {
-            loop {
-                let data = recieve.recv().await.unwrap_or_default();
-
+            while let Some(message) = recieve.recv().await {
                 reader_sender
                     .lock()
                     .await
-                    .send(Message::binary(data))
+                    .send(message)
                     .await
                     .unwrap_or_default();
             }
+
+            // the listener was dropped for falling behind
+            let close = CloseFrame {
+                code: close_code::AGAIN,
+                reason: "too slow to keep up".into(),
+            };
+            reader_sender
+                .lock()
+                .await
+                .send(Message::Close(Some(close)))
+                .await
+                .unwrap_or_default();
         });
 
         let pinger_sender = Arc::clone(&sender);
@@ -0,0 +1,103 @@
+use crate::data::Data;
+use axum::{extract::State, http::HeaderMap};
+use raster::DecodeError;
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+use tokio::sync::Mutex;
+
+/// Counters since the server started.
+#[derive(Default)]
+pub struct Metrics {
+    pub decode_errors: BTreeMap<DecodeError, u64>,
+    /// Decoded messages that were not drawn, e.g. for a locked layer.
+    pub dropped: u64,
+    /// Decoded messages over the rate limit.
+    pub rate_limited: u64,
+    /// Frames and pastes refused for bans or maintenance.
+    pub refused: u64,
+    /// Listeners dropped because they could not keep up with drawing.
+    pub lagging: u64,
+}
+
+/// How often each error occurs in `errors`.
+pub fn count_errors(errors: &[DecodeError]) -> BTreeMap<DecodeError, usize> {
+    let mut counts = BTreeMap::new();
+    for err in errors {
+        *counts.entry(*err).or_default() += 1;
+    }
+
+    counts
+}
+
+/// The metrics in the Prometheus text format.
+pub async fn metrics(State(data): State<Arc<Mutex<Data>>>) -> (HeaderMap, String) {
+    let data = data.lock().await;
+    let metrics = &data.metrics;
+
+    let mut body = String::new();
+    let mut counter = |name: &str, help: &str, values: &[(Option<&str>, u64)]| {
+        writeln!(body, "# HELP draw_together_{name} {help}").unwrap();
+        writeln!(body, "# TYPE draw_together_{name} counter").unwrap();
+        for (reason, value) in values {
+            match reason {
+                Some(reason) => {
+                    writeln!(body, "draw_together_{name}{{reason=\"{reason}\"}} {value}")
+                }
+                None => writeln!(body, "draw_together_{name} {value}"),
+            }
+            .unwrap();
+        }
+    };
+
+    counter("messages_total", "Messages broadcast.", &[(None, data.seq)]);
+    counter(
+        "decode_errors_total",
+        "Messages that could not be decoded, by reason.",
+        &DecodeError::ALL.map(|err| {
+            (
+                Some(err.name()),
+                metrics.decode_errors.get(&err).copied().unwrap_or_default(),
+            )
+        }),
+    );
+    counter(
+        "dropped_messages_total",
+        "Decoded messages that were not drawn.",
+        &[(None, metrics.dropped)],
+    );
+    counter(
+        "rate_limited_messages_total",
+        "Decoded messages over the rate limit.",
+        &[(None, metrics.rate_limited)],
+    );
+    counter(
+        "refused_frames_total",
+        "Frames and pastes refused for bans or maintenance.",
+        &[(None, metrics.refused)],
+    );
+    counter(
+        "lagging_listeners_total",
+        "Websocket connections and event streams dropped for falling behind.",
+        &[(None, metrics.lagging)],
+    );
+
+    writeln!(
+        body,
+        "# HELP draw_together_connections Open websocket connections and event streams."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE draw_together_connections gauge").unwrap();
+    writeln!(
+        body,
+        "draw_together_connections {}",
+        data.listeners
+            .iter()
+            .filter(|listener| listener.is_open())
+            .count()
+    )
+    .unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", "text/plain; version=0.0.4".parse().unwrap());
+
+    (headers, body)
+}
@@ -0,0 +1,157 @@
+use crate::protocol::ServerControl;
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Who may draw and how much, as configured by the operator.
+#[derive(Default)]
+pub struct Moderation {
+    /// Addresses that may watch but not draw.
+    pub banned: Vec<IpAddr>,
+    /// Drawing is paused with this notice, if set.
+    pub maintenance: Option<String>,
+    /// Most messages an address may draw per second, if set.
+    pub rate_limit: Option<usize>,
+}
+
+impl Moderation {
+    /// The notice for `ip` if it may not draw at all right now.
+    pub fn refuse(&self, ip: IpAddr) -> Option<ServerControl> {
+        if let Some(message) = &self.maintenance {
+            return Some(ServerControl::Maintenance {
+                message: message.clone(),
+            });
+        }
+
+        self.banned.contains(&ip).then_some(ServerControl::Banned)
+    }
+}
+
+/// Parses addresses separated by commas.
+pub fn parse_banned(spec: &str) -> Result<Vec<IpAddr>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse()
+                .map_err(|_| format!("invalid address {entry}"))
+        })
+        .collect()
+}
+
+/// Counts the messages an address draws within each second.
+pub struct RateLimiter {
+    limit: Option<usize>,
+    window: Instant,
+    count: usize,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            window: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// How many of `count` new messages may be drawn.
+    pub fn take(&mut self, count: usize) -> usize {
+        let Some(limit) = self.limit else {
+            return count;
+        };
+
+        if self.window.elapsed() >= Duration::from_secs(1) {
+            self.window = Instant::now();
+            self.count = 0;
+        }
+
+        let allowed = count.min(limit - self.count);
+        self.count += allowed;
+
+        allowed
+    }
+
+    /// Whether nothing was drawn within the last second, so the limiter can
+    /// be dropped.
+    pub fn is_idle(&self) -> bool {
+        self.window.elapsed() >= Duration::from_secs(1)
+    }
+
+    /// How long until more messages may be drawn.
+    pub fn retry_after(&self) -> Duration {
+        Duration::from_secs(1).saturating_sub(self.window.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Moves the window of `limiter` a second into the past.
+    fn elapse(limiter: &mut RateLimiter) {
+        limiter.window -= Duration::from_secs(1);
+    }
+
+    #[test]
+    fn parses_banned_addresses() {
+        assert_eq!(
+            parse_banned(" 203.0.113.7, 2001:db8::1,,"),
+            Ok(vec![
+                "203.0.113.7".parse().unwrap(),
+                "2001:db8::1".parse().unwrap()
+            ])
+        );
+        assert_eq!(parse_banned(""), Ok(Vec::new()));
+        assert_eq!(
+            parse_banned("203.0.113.7,localhost"),
+            Err("invalid address localhost".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_banned_addresses_and_everyone_in_maintenance() {
+        let banned = "203.0.113.7".parse().unwrap();
+        let other = "203.0.113.8".parse().unwrap();
+        let mut moderation = Moderation {
+            banned: vec![banned],
+            ..Moderation::default()
+        };
+        assert!(matches!(
+            moderation.refuse(banned),
+            Some(ServerControl::Banned)
+        ));
+        assert!(moderation.refuse(other).is_none());
+
+        moderation.maintenance = Some("back soon".to_string());
+        assert!(matches!(
+            moderation.refuse(other),
+            Some(ServerControl::Maintenance { message }) if message == "back soon"
+        ));
+    }
+
+    #[test]
+    fn limits_messages_per_second() {
+        let mut limiter = RateLimiter::new(Some(10));
+        assert_eq!(limiter.take(4), 4);
+        assert_eq!(limiter.take(8), 6);
+        assert_eq!(limiter.take(1), 0);
+        assert!(!limiter.is_idle());
+        assert!(limiter.retry_after() > Duration::ZERO);
+
+        elapse(&mut limiter);
+        assert!(limiter.is_idle());
+        assert_eq!(limiter.retry_after(), Duration::ZERO);
+        assert_eq!(limiter.take(12), 10);
+        assert!(!limiter.is_idle());
+    }
+
+    #[test]
+    fn takes_everything_without_a_limit() {
+        let mut limiter = RateLimiter::new(None);
+        assert_eq!(limiter.take(usize::MAX), usize::MAX);
+        assert_eq!(limiter.take(1), 1);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::{data::Data, protocol::ServerControl};
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Query, State},
+    http::StatusCode,
+};
+use image::{ImageFormat, ImageReader, Limits, RgbaImage, imageops::FilterType};
+use raster::{
+    Action, Batch, Canvas, ClientMessage, MAX_IMAGE_PIXELS, RESOLUTION_HEIGHT, RESOLUTION_WIDTH,
+    Style,
+};
+use serde::Deserialize;
+use std::{io::Cursor, net::SocketAddr, sync::Arc};
+use tokio::sync::Mutex;
+
+/// Largest upload accepted, in bytes.
+pub const MAX_UPLOAD_BYTES: usize = 16 * 1024 * 1024;
+/// Largest width or height of an upload, before and after scaling.
+const MAX_IMAGE_SIZE: u32 = 4096;
+
+/// Thresholds for dithering transparency, tiled over the canvas.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[derive(Deserialize)]
+pub struct PasteQuery {
+    x: u16,
+    y: u16,
+    scale: Option<f32>,
+    #[serde(default)]
+    dither: bool,
+    #[serde(default)]
+    layer: u8,
+}
+
+/// Pastes a PNG or JPEG image with its top left corner at `(x, y)` onto
+/// `layer`, the first one by default.
+/// Transparent pixels keep what is already on the canvas, either cut off at
+/// half opacity or, with `dither`, as an ordered dither pattern.
+/// Every image message, a strip of rows or a span of one, counts against the
+/// rate limit of the address like in [`crate::draw::draw`]; what is over it
+/// is left out.
+pub async fn paste(
+    State(data): State<Arc<Mutex<Data>>>,
+    ConnectInfo(who): ConnectInfo<SocketAddr>,
+    Query(query): Query<PasteQuery>,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    {
+        let mut data = data.lock().await;
+        let refused = data.moderation.refuse(who.ip());
+        if refused.is_some() {
+            data.metrics.refused += 1;
+        }
+
+        match refused {
+            Some(ServerControl::Maintenance { message }) => {
+                return Err((StatusCode::SERVICE_UNAVAILABLE, message));
+            }
+            Some(_) => return Err((StatusCode::FORBIDDEN, "banned".to_string())),
+            None => {}
+        }
+    }
+
+    if query.x as usize >= RESOLUTION_WIDTH || query.y as usize >= RESOLUTION_HEIGHT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "position is outside of the canvas".to_string(),
+        ));
+    }
+
+    let scale = query.scale.unwrap_or(1.0);
+    if !(scale > 0.0 && scale.is_finite()) {
+        return Err((StatusCode::BAD_REQUEST, "invalid scale".to_string()));
+    }
+
+    let image = tokio::task::spawn_blocking(move || decode(&body, scale))
+        .await
+        .unwrap()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let mut data = data.lock().await;
+    let messages = {
+        let layers = data.data.read().await;
+        let layer = match layers.layer(query.layer) {
+            Some(layer) if !layer.locked => layer,
+            Some(_) => return Err((StatusCode::BAD_REQUEST, "layer is locked".to_string())),
+            None => return Err((StatusCode::BAD_REQUEST, "unknown layer".to_string())),
+        };
+
+        compose(
+            layer.canvas(),
+            &image,
+            query.x,
+            query.y,
+            query.dither,
+            query.layer,
+        )
+    };
+    let received = data
+        .receive(
+            who.ip(),
+            Batch {
+                messages,
+                errors: Vec::new(),
+            },
+        )
+        .await;
+
+    for notice in received.notices {
+        match notice {
+            ServerControl::Maintenance { message } => {
+                return Err((StatusCode::SERVICE_UNAVAILABLE, message));
+            }
+            ServerControl::Banned => {
+                return Err((StatusCode::FORBIDDEN, "banned".to_string()));
+            }
+            ServerControl::RateLimited {
+                dropped,
+                retry_after_ms,
+            } => {
+                return Err((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!("{dropped} strips were left out, retry after {retry_after_ms} ms"),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn decode(body: &[u8], scale: f32) -> Result<RgbaImage, String> {
+    let mut reader = ImageReader::new(Cursor::new(body))
+        .with_guessed_format()
+        .map_err(|err| err.to_string())?;
+    if !matches!(reader.format(), Some(ImageFormat::Png | ImageFormat::Jpeg)) {
+        return Err("only png and jpeg images are supported".to_string());
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIZE);
+    limits.max_image_height = Some(MAX_IMAGE_SIZE);
+    reader.limits(limits);
+
+    let image = reader.decode().map_err(|err| err.to_string())?.into_rgba8();
+    if scale == 1.0 {
+        return Ok(image);
+    }
+
+    let width = (image.width() as f32 * scale).round();
+    let height = (image.height() as f32 * scale).round();
+    if width < 1.0 || height < 1.0 {
+        return Err("image is empty after scaling".to_string());
+    }
+    if width > MAX_IMAGE_SIZE as f32 || height > MAX_IMAGE_SIZE as f32 {
+        return Err("image is too large after scaling".to_string());
+    }
+
+    Ok(image::imageops::resize(
+        &image,
+        width as u32,
+        height as u32,
+        FilterType::Triangle,
+    ))
+}
+
+/// Flattens the part of `image` that is on the canvas onto what is there
+/// and splits it into image messages of whole rows.
+/// Where a pixel left out of the image is over one of the canvas that is not
+/// opaque, the rows are split into spans of the pixels that are painted
+/// instead, so the canvas stays transparent there.
+fn compose(
+    canvas: &Canvas,
+    image: &RgbaImage,
+    x: u16,
+    y: u16,
+    dither: bool,
+    layer: u8,
+) -> Vec<ClientMessage> {
+    let width = (image.width() as usize).min(canvas.width() - x as usize);
+    let height = (image.height() as usize).min(canvas.height() - y as usize);
+    let strip = MAX_IMAGE_PIXELS / width;
+
+    let pixel = |column: usize, row: usize| {
+        let canvas_x = x as usize + column;
+        let canvas_y = y as usize + row;
+        let [r, g, b, a] = image.get_pixel(column as u32, row as u32).0;
+        let threshold = match dither {
+            true => BAYER[canvas_y % 4][canvas_x % 4] * 16 + 8,
+            false => 127,
+        };
+
+        match a > threshold {
+            true => Some([r, g, b]),
+            false if canvas.alpha(canvas_x, canvas_y) == Some(0xFF) => {
+                canvas.pixel(canvas_x, canvas_y)
+            }
+            false => None,
+        }
+    };
+
+    let mut messages = Vec::with_capacity(height.div_ceil(strip));
+    for start_row in (0..height).step_by(strip) {
+        let rows = start_row..(start_row + strip).min(height);
+        let pixels = rows
+            .clone()
+            .flat_map(|row| (0..width).map(move |column| pixel(column, row)))
+            .collect::<Option<Vec<_>>>();
+
+        if let Some(pixels) = pixels {
+            messages.push(image_message(x, y + start_row as u16, width, pixels, layer));
+            continue;
+        }
+
+        for row in rows {
+            let mut column = 0;
+            while column < width {
+                let span = (column..width)
+                    .map_while(|column| pixel(column, row))
+                    .collect::<Vec<_>>();
+
+                let length = span.len();
+                if length > 0 {
+                    messages.push(image_message(
+                        x + column as u16,
+                        y + row as u16,
+                        length,
+                        span,
+                        layer,
+                    ));
+                }
+                column += length + 1;
+            }
+        }
+    }
+
+    messages
+}
+
+fn image_message(x: u16, y: u16, width: usize, pixels: Vec<[u8; 3]>, layer: u8) -> ClientMessage {
+    ClientMessage {
+        action: Action::DrawImage {
+            width: width as u16,
+            pixels,
+        },
+        x,
+        y,
+        height: 1,
+        color: [0; 3],
+        style: Style::default(),
+        layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use raster::Layers;
+
+    /// Four by two pixels, opaque red on the left and transparent on the
+    /// right.
+    fn half_transparent() -> RgbaImage {
+        RgbaImage::from_fn(4, 2, |x, _| match x < 2 {
+            true => Rgba([0xFF, 0, 0, 0xFF]),
+            false => Rgba([0, 0xFF, 0, 0]),
+        })
+    }
+
+    #[test]
+    fn keeps_the_bottom_layer_under_transparent_pixels() {
+        let mut layers = Layers::new(16, 16, 2);
+        let messages = compose(
+            layers.layer(0).unwrap().canvas(),
+            &half_transparent(),
+            2,
+            3,
+            false,
+            0,
+        );
+        assert_eq!(messages.len(), 1);
+
+        for message in &messages {
+            layers.rasterise(message);
+        }
+        let canvas = layers.layer(0).unwrap().canvas();
+        assert_eq!(canvas.pixel(3, 4), Some([0xFF, 0, 0]));
+        assert_eq!(canvas.pixel(4, 4), Some([0xFF; 3]));
+    }
+
+    #[test]
+    fn leaves_upper_layers_transparent_under_transparent_pixels() {
+        let mut layers = Layers::new(16, 16, 2);
+        let messages = compose(
+            layers.layer(1).unwrap().canvas(),
+            &half_transparent(),
+            2,
+            3,
+            false,
+            1,
+        );
+        assert_eq!(messages.len(), 2);
+
+        for message in &messages {
+            layers.rasterise(message);
+        }
+        let canvas = layers.layer(1).unwrap().canvas();
+        for y in 3..5 {
+            for x in 2..4 {
+                assert_eq!(canvas.alpha(x, y), Some(0xFF));
+                assert_eq!(canvas.pixel(x, y), Some([0xFF, 0, 0]));
+            }
+            for x in 4..6 {
+                assert_eq!(canvas.alpha(x, y), Some(0));
+            }
+        }
+    }
+}
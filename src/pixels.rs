@@ -0,0 +1,143 @@
+use crate::data::Data;
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use image::{ImageFormat, RgbImage};
+use raster::{RESOLUTION_HEIGHT, RESOLUTION_WIDTH};
+use serde::{Deserialize, Serialize};
+use std::{io::Cursor, sync::Arc};
+use tokio::sync::Mutex;
+
+#[derive(Deserialize)]
+pub struct PixelQuery {
+    x: usize,
+    y: usize,
+}
+
+#[derive(Serialize)]
+pub struct Pixel {
+    x: usize,
+    y: usize,
+    color: [u8; 3],
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// RGB, row by row, like `/history_2.raw`.
+    #[default]
+    Raw,
+    Png,
+    Json,
+}
+
+#[derive(Deserialize)]
+pub struct RegionQuery {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Serialize)]
+pub struct Region {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    /// Row by row.
+    pixels: Vec<[u8; 3]>,
+}
+
+/// The colour of a single pixel with every visible layer composited.
+pub async fn pixel(
+    State(data): State<Arc<Mutex<Data>>>,
+    Query(query): Query<PixelQuery>,
+) -> Result<Json<Pixel>, (StatusCode, String)> {
+    let data = data.lock().await;
+    let color = data
+        .data
+        .read()
+        .await
+        .composite_pixel(query.x, query.y)
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "position is outside of the canvas".to_string(),
+        ))?;
+
+    Ok(Json(Pixel {
+        x: query.x,
+        y: query.y,
+        color,
+    }))
+}
+
+/// A rectangle of the canvas with every visible layer composited, as raw
+/// RGB, a PNG or JSON.
+pub async fn region(
+    State(data): State<Arc<Mutex<Data>>>,
+    Query(query): Query<RegionQuery>,
+) -> Result<(HeaderMap, Body), (StatusCode, String)> {
+    if query.w == 0
+        || query.h == 0
+        || query.x.saturating_add(query.w) > RESOLUTION_WIDTH
+        || query.y.saturating_add(query.h) > RESOLUTION_HEIGHT
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "region is empty or outside of the canvas".to_string(),
+        ));
+    }
+
+    // copied, so drawing is not held up while the layers are composited
+    let region = {
+        let data = Arc::clone(&data.lock().await.data);
+        let layers = data.read().await;
+        layers.crop(query.x, query.y, query.w, query.h).unwrap()
+    };
+    let rgb = region.composite().into_bytes();
+
+    let mut headers = HeaderMap::new();
+    let body = match query.format {
+        Format::Raw => {
+            headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
+            Body::from(rgb)
+        }
+        Format::Png => {
+            headers.insert("Content-Type", "image/png".parse().unwrap());
+            let png = tokio::task::spawn_blocking(move || {
+                let image = RgbImage::from_raw(query.w as u32, query.h as u32, rgb).unwrap();
+                let mut png = Cursor::new(Vec::new());
+                image.write_to(&mut png, ImageFormat::Png).map(|_| png)
+            })
+            .await
+            .unwrap()
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+            Body::from(png.into_inner())
+        }
+        Format::Json => {
+            headers.insert("Content-Type", "application/json".parse().unwrap());
+            Body::from(
+                serde_json::to_vec(&Region {
+                    x: query.x,
+                    y: query.y,
+                    width: query.w,
+                    height: query.h,
+                    pixels: rgb
+                        .chunks_exact(3)
+                        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                        .collect(),
+                })
+                .unwrap(),
+            )
+        }
+    };
+
+    Ok((headers, body))
+}
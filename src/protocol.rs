@@ -0,0 +1,336 @@
+use raster::{
+    Batch, ClientMessage, DecodeError, MAX_IMAGE_PIXELS, MAX_LAYERS, MAX_POLYGON_SIDES, MAX_SPANS,
+    MAX_STAMP_SCALE, MAX_STROKE_POINTS, MAX_TEXT_LENGTH, MAX_TEXT_SCALE, RESOLUTION_HEIGHT,
+    RESOLUTION_WIDTH,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Only the 7 byte legacy messages.
+pub const VERSION_LEGACY: u8 = 1;
+/// The legacy and the extended messages.
+pub const VERSION_EXTENDED: u8 = 2;
+/// Every version the server speaks, oldest first.
+pub const VERSIONS: [u8; 2] = [VERSION_LEGACY, VERSION_EXTENDED];
+
+/// Control messages from clients, sent as JSON text frames next to the
+/// binary frames of drawing messages.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientControl {
+    /// The first message of a client that negotiates, clients that never
+    /// send one are old ones that only speak the legacy version. Notices are
+    /// only sent to clients that ask for the `notices` feature.
+    Hello {
+        versions: Vec<u8>,
+        #[serde(default)]
+        features: Vec<String>,
+    },
+    /// Messages to draw, as an alternative to binary frames, see
+    /// [`decode_json`].
+    Draw { messages: Vec<serde_json::Value> },
+    /// Only sends messages that can touch this rectangle from now on, or
+    /// every message again if all of it is left out.
+    Viewport {
+        x: Option<i32>,
+        y: Option<i32>,
+        w: Option<i32>,
+        h: Option<i32>,
+    },
+}
+
+/// Control messages to clients, sent as JSON text frames.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerControl {
+    Welcome {
+        version: u8,
+        width: usize,
+        height: usize,
+        /// The features the client asked for that this server has.
+        features: Vec<&'static str>,
+        limits: Limits,
+        /// How many messages have been broadcast so far.
+        seq: u64,
+    },
+    /// None of the versions of the hello are spoken, the connection is
+    /// closed after this.
+    Unsupported { versions: [u8; 2] },
+    /// Some messages of a frame were not drawn: `errors` counts the ones
+    /// that could not be decoded by reason, see [`raster::DecodeError`], and
+    /// `dropped` ones were refused, e.g. for a locked layer or unknown stamp.
+    Rejected {
+        errors: BTreeMap<&'static str, usize>,
+        dropped: usize,
+    },
+    /// Messages over the rate limit were not drawn.
+    RateLimited { dropped: usize, retry_after_ms: u64 },
+    /// This address may watch but not draw, nothing of the frame was drawn.
+    Banned,
+    /// Drawing is paused, nothing of the frame was drawn.
+    Maintenance { message: String },
+}
+
+#[derive(Serialize)]
+pub struct Limits {
+    max_stroke_points: usize,
+    max_spans: usize,
+    max_text_length: usize,
+    max_text_scale: u8,
+    max_image_pixels: usize,
+    max_polygon_sides: u8,
+    max_stamp_scale: u8,
+    max_layers: u8,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_stroke_points: MAX_STROKE_POINTS,
+            max_spans: MAX_SPANS,
+            max_text_length: MAX_TEXT_LENGTH,
+            max_text_scale: MAX_TEXT_SCALE,
+            max_image_pixels: MAX_IMAGE_PIXELS,
+            max_polygon_sides: MAX_POLYGON_SIDES,
+            max_stamp_scale: MAX_STAMP_SCALE,
+            max_layers: MAX_LAYERS,
+        }
+    }
+}
+
+impl ServerControl {
+    /// Answers a hello with the newest version both sides speak and the
+    /// requested features out of `available`.
+    pub fn welcome(
+        versions: &[u8],
+        features: &[String],
+        available: &[&'static str],
+        seq: u64,
+    ) -> Self {
+        let Some(version) = VERSIONS
+            .into_iter()
+            .rev()
+            .find(|version| versions.contains(version))
+        else {
+            return Self::Unsupported { versions: VERSIONS };
+        };
+
+        Self::Welcome {
+            version,
+            width: RESOLUTION_WIDTH,
+            height: RESOLUTION_HEIGHT,
+            features: available
+                .iter()
+                .copied()
+                .filter(|feature| features.iter().any(|requested| requested == feature))
+                .collect(),
+            limits: Limits::default(),
+            seq,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Drawn messages for clients that asked for the `json` feature, sent as
+/// text frames instead of binary ones.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "draw")]
+pub struct DrawFrame<'a> {
+    /// How many messages have been broadcast, including these.
+    seq: u64,
+    messages: Vec<&'a ClientMessage>,
+}
+
+impl<'a> DrawFrame<'a> {
+    pub fn new(seq: u64, messages: impl IntoIterator<Item = &'a ClientMessage>) -> Self {
+        Self {
+            seq,
+            messages: messages.into_iter().collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Parses the messages of a [`ClientControl::Draw`] and checks them like
+/// binary ones are when decoded. Objects that are not shaped like a
+/// message at all are [`DecodeError::InvalidValue`].
+pub fn decode_json(messages: Vec<serde_json::Value>) -> Batch {
+    let mut batch = Batch::default();
+
+    for message in messages {
+        let message = serde_json::from_value::<ClientMessage>(message)
+            .map_err(|_| DecodeError::InvalidValue)
+            .and_then(|message| message.validate().map(|_| message));
+
+        match message {
+            Ok(message) => batch.messages.push(message),
+            Err(err) => batch.errors.push(err),
+        }
+    }
+
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raster::{Action, Style};
+
+    fn welcome(versions: &[u8], features: &[&str]) -> ServerControl {
+        let features = features
+            .iter()
+            .map(|feature| feature.to_string())
+            .collect::<Vec<_>>();
+        ServerControl::welcome(versions, &features, &["notices", "json", "layers"], 42)
+    }
+
+    #[test]
+    fn picks_the_newest_shared_version() {
+        for (versions, expected) in [
+            (&[1, 2][..], VERSION_EXTENDED),
+            (&[2, 1], VERSION_EXTENDED),
+            (&[1], VERSION_LEGACY),
+            (&[1, 3], VERSION_LEGACY),
+            (&[2, 9], VERSION_EXTENDED),
+        ] {
+            let ServerControl::Welcome { version, seq, .. } = welcome(versions, &[]) else {
+                panic!("{versions:?} was not welcomed");
+            };
+            assert_eq!(version, expected, "{versions:?}");
+            assert_eq!(seq, 42);
+        }
+    }
+
+    #[test]
+    fn refuses_unknown_versions() {
+        for versions in [&[][..], &[0], &[3, 4]] {
+            assert!(matches!(
+                welcome(versions, &[]),
+                ServerControl::Unsupported { versions: VERSIONS }
+            ));
+        }
+    }
+
+    #[test]
+    fn only_shares_available_features() {
+        let ServerControl::Welcome { features, .. } =
+            welcome(&[2], &["json", "stamps", "notices", "NOTICES"])
+        else {
+            panic!("not welcomed");
+        };
+        assert_eq!(features, ["notices", "json"]);
+    }
+
+    #[test]
+    fn parses_hellos() {
+        let hello = serde_json::from_str(r#"{"type":"hello","versions":[1,2]}"#);
+        assert!(matches!(
+            hello,
+            Ok(ClientControl::Hello { versions, features }) if versions == [1, 2] && features.is_empty()
+        ));
+
+        assert!(serde_json::from_str::<ClientControl>(r#"{"type":"hello"}"#).is_err());
+        assert!(serde_json::from_str::<ClientControl>(r#"{"type":"goodbye"}"#).is_err());
+    }
+
+    #[test]
+    fn parses_draws() {
+        let draw = serde_json::from_str::<ClientControl>(
+            r#"{"type":"draw","messages":[{"action":{"type":"draw_cube_normal"},"x":10,"y":10,"height":5,"color":[255,0,0]}]}"#,
+        );
+        let Ok(ClientControl::Draw { messages }) = draw else {
+            panic!("draw was not parsed");
+        };
+
+        let batch = decode_json(messages);
+        assert_eq!(
+            batch.messages,
+            [ClientMessage {
+                action: Action::DrawCubeNormal,
+                x: 10,
+                y: 10,
+                height: 5,
+                color: [255, 0, 0],
+                style: Style::default(),
+                layer: 0,
+            }]
+        );
+        assert!(batch.errors.is_empty());
+
+        assert!(serde_json::from_str::<ClientControl>(r#"{"type":"draw"}"#).is_err());
+    }
+
+    #[test]
+    fn skips_invalid_json_messages() {
+        let messages = serde_json::from_str(
+            r#"[
+                {"action":{"type":"draw_line","x2":50,"y2":60},"x":10,"y":10,"height":3,"color":[0,0,255],"layer":1},
+                {"action":{"type":"draw_cube_normal"},"x":60000,"y":10,"height":5,"color":[255,0,0]},
+                {"action":{"type":"teleport"},"x":10,"y":10,"height":5,"color":[255,0,0]},
+                {"x":10,"y":10},
+                42
+            ]"#,
+        )
+        .unwrap();
+
+        let batch = decode_json(messages);
+        assert_eq!(batch.messages.len(), 1);
+        assert_eq!(batch.messages[0].layer, 1);
+        assert_eq!(
+            batch.errors,
+            [
+                DecodeError::OutOfBounds,
+                DecodeError::InvalidValue,
+                DecodeError::InvalidValue,
+                DecodeError::InvalidValue,
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_viewports() {
+        let viewport =
+            serde_json::from_str(r#"{"type":"viewport","x":-10,"y":20,"w":300,"h":200}"#);
+        assert!(matches!(
+            viewport,
+            Ok(ClientControl::Viewport {
+                x: Some(-10),
+                y: Some(20),
+                w: Some(300),
+                h: Some(200)
+            })
+        ));
+
+        let viewport = serde_json::from_str(r#"{"type":"viewport"}"#);
+        assert!(matches!(
+            viewport,
+            Ok(ClientControl::Viewport {
+                x: None,
+                y: None,
+                w: None,
+                h: None
+            })
+        ));
+
+        assert!(serde_json::from_str::<ClientControl>(r#"{"type":"viewport","x":"10"}"#).is_err());
+    }
+
+    #[test]
+    fn serializes_notices() {
+        let rejected = ServerControl::Rejected {
+            errors: BTreeMap::from([("truncated", 1), ("out_of_bounds", 2)]),
+            dropped: 0,
+        };
+        assert_eq!(
+            rejected.to_json(),
+            r#"{"type":"rejected","errors":{"out_of_bounds":2,"truncated":1},"dropped":0}"#
+        );
+        assert_eq!(ServerControl::Banned.to_json(), r#"{"type":"banned"}"#);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::data::Data;
+use axum::{Json, extract::State};
+use raster::{MAX_STAMP_SIZE, Stamp};
+use serde::Serialize;
+use std::{path::Path, sync::Arc};
+use tokio::sync::Mutex;
+
+/// A stamp and the name it is listed under, from its file name.
+pub struct NamedStamp {
+    pub name: String,
+    pub stamp: Stamp,
+}
+
+#[derive(Serialize)]
+pub struct StampInfo {
+    id: u16,
+    name: String,
+    width: usize,
+    height: usize,
+}
+
+/// Loads every PNG in `dir` as a stamp, in the order of their file names,
+/// which is also the id they are drawn with. Pixels that are less than half
+/// opaque are left out of the stamp.
+pub fn load(dir: &Path) -> Vec<NamedStamp> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut stamps = Vec::with_capacity(paths.len());
+    for path in paths.into_iter().take(u16::MAX as usize + 1) {
+        let image = match image::open(&path) {
+            Ok(image) => image.into_rgba8(),
+            Err(err) => {
+                println!("skipping stamp {}: {err}", path.display());
+                continue;
+            }
+        };
+
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b, a] = pixel.0;
+                (a > 127).then_some([r, g, b])
+            })
+            .collect();
+
+        let Some(stamp) = Stamp::new(image.width() as usize, image.height() as usize, pixels)
+        else {
+            println!(
+                "skipping stamp {}: larger than {MAX_STAMP_SIZE}x{MAX_STAMP_SIZE}",
+                path.display()
+            );
+            continue;
+        };
+
+        stamps.push(NamedStamp {
+            name: path.file_stem().unwrap().to_string_lossy().into_owned(),
+            stamp,
+        });
+    }
+
+    stamps
+}
+
+/// Lists the stamps clients can draw, by id.
+pub async fn list(State(data): State<Arc<Mutex<Data>>>) -> Json<Vec<StampInfo>> {
+    let data = data.lock().await;
+
+    Json(
+        data.stamps
+            .iter()
+            .enumerate()
+            .map(|(id, stamp)| StampInfo {
+                id: id as u16,
+                name: stamp.name.clone(),
+                width: stamp.stamp.width(),
+                height: stamp.stamp.height(),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,967 @@
+use axum::{
+    body::Bytes,
+    extract::{
+        FromRef, FromRequestParts,
+        ws::{self, CloseFrame, Message},
+    },
+    http::{HeaderMap, Method, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use flate2::{Compress, Decompress, FlushCompress, FlushDecompress, Status};
+use futures_util::{Sink, Stream};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use std::{
+    io::{self, Cursor},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{
+        self as ts,
+        handshake::derive_accept_key,
+        protocol::{
+            Role,
+            frame::{
+                Frame, FrameHeader,
+                coding::{Data, OpCode},
+            },
+        },
+    },
+};
+
+pub const DEFAULT_THRESHOLD: usize = 16;
+
+/// The largest frame and message a client may send, as in tungstenite.
+const MAX_FRAME_SIZE: usize = 16 << 20;
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// How frames are compressed for clients that offer permessage-deflate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compression {
+    /// The deflate level, 1-9.
+    pub level: u32,
+    /// Frames of fewer bytes are sent as they are, as they barely shrink.
+    pub threshold: usize,
+}
+
+/// The permessage-deflate parameters agreed on with a client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Deflate {
+    compression: Compression,
+    /// Every message is compressed on its own instead of referring back to
+    /// the ones before it.
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+/// The first offer of permessage-deflate in `offers` that can be accepted.
+fn negotiate(offers: &str, compression: Compression) -> Option<Deflate> {
+    offers.split(',').find_map(|offer| {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next()? != "permessage-deflate" {
+            return None;
+        }
+
+        let mut deflate = Deflate {
+            compression,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        };
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            match (name, value) {
+                ("server_no_context_takeover", None) => deflate.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => deflate.client_no_context_takeover = true,
+                // the window is always the largest, 15 bits, which also
+                // inflates anything compressed with a smaller one
+                ("server_max_window_bits", Some("15")) | ("client_max_window_bits", _) => {}
+                _ => return None,
+            }
+        }
+
+        Some(deflate)
+    })
+}
+
+impl Deflate {
+    fn response(&self) -> String {
+        let mut response = "permessage-deflate".to_string();
+        if self.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+
+        response
+    }
+
+    fn deflater(&self) -> Deflater {
+        Deflater {
+            compress: Compress::new(flate2::Compression::new(self.compression.level), false),
+            threshold: self.compression.threshold,
+            no_context_takeover: self.server_no_context_takeover,
+        }
+    }
+
+    fn inflater(&self) -> Inflater {
+        Inflater {
+            decompress: Decompress::new(false),
+            no_context_takeover: self.client_no_context_takeover,
+            message: None,
+        }
+    }
+}
+
+/// The values of every `name` header, joined into one list.
+fn lists(headers: &HeaderMap, name: header::HeaderName) -> String {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Upgrades a request to a websocket, with axum's `WebSocketUpgrade` unless
+/// the client offers compression that is configured, which axum cannot
+/// negotiate.
+pub enum WebSocketUpgrade {
+    Plain(ws::WebSocketUpgrade),
+    Deflate {
+        key: Bytes,
+        deflate: Deflate,
+        on_upgrade: OnUpgrade,
+    },
+}
+
+impl<S> FromRequestParts<S> for WebSocketUpgrade
+where
+    S: Send + Sync,
+    Option<Compression>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let offers = lists(&parts.headers, header::SEC_WEBSOCKET_EXTENSIONS);
+        let Some(deflate) = Option::<Compression>::from_ref(state)
+            .and_then(|compression| negotiate(&offers, compression))
+        else {
+            return ws::WebSocketUpgrade::from_request_parts(parts, state)
+                .await
+                .map(Self::Plain)
+                .map_err(IntoResponse::into_response);
+        };
+
+        let contains = |name, token: &str| {
+            lists(&parts.headers, name)
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        };
+        let reject = |status: StatusCode, reason: &'static str| (status, reason).into_response();
+
+        if parts.method != Method::GET {
+            return Err(reject(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "websockets are opened with GET",
+            ));
+        }
+        if !contains(header::CONNECTION, "upgrade") || !contains(header::UPGRADE, "websocket") {
+            return Err(reject(StatusCode::BAD_REQUEST, "not a websocket upgrade"));
+        }
+        if parts
+            .headers
+            .get(header::SEC_WEBSOCKET_VERSION)
+            .is_none_or(|version| version != "13")
+        {
+            return Err(reject(
+                StatusCode::BAD_REQUEST,
+                "unsupported websocket version",
+            ));
+        }
+        let Some(key) = parts.headers.get(header::SEC_WEBSOCKET_KEY) else {
+            return Err(reject(StatusCode::BAD_REQUEST, "missing Sec-WebSocket-Key"));
+        };
+
+        Ok(Self::Deflate {
+            key: Bytes::copy_from_slice(key.as_bytes()),
+            deflate,
+            on_upgrade: parts.extensions.remove::<OnUpgrade>().ok_or(reject(
+                StatusCode::UPGRADE_REQUIRED,
+                "connection cannot be upgraded",
+            ))?,
+        })
+    }
+}
+
+impl WebSocketUpgrade {
+    /// Switches protocols and runs `callback` with the websocket.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (key, deflate, on_upgrade) = match self {
+            Self::Plain(upgrade) => {
+                return upgrade.on_upgrade(|socket| {
+                    callback(WebSocket {
+                        inner: Inner::Plain(socket),
+                    })
+                });
+            }
+            Self::Deflate {
+                key,
+                deflate,
+                on_upgrade,
+            } => (key, deflate, on_upgrade),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, "upgrade".parse().unwrap());
+        headers.insert(header::UPGRADE, "websocket".parse().unwrap());
+        headers.insert(
+            header::SEC_WEBSOCKET_ACCEPT,
+            derive_accept_key(&key).parse().unwrap(),
+        );
+        headers.insert(
+            header::SEC_WEBSOCKET_EXTENSIONS,
+            deflate.response().parse().unwrap(),
+        );
+
+        tokio::spawn(async move {
+            let Ok(upgraded) = on_upgrade.await else {
+                return;
+            };
+
+            let stream = Inflate::new(TokioIo::new(upgraded), deflate.inflater());
+            let stream = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+
+            callback(WebSocket {
+                inner: Inner::Deflate {
+                    stream,
+                    deflater: deflate.deflater(),
+                },
+            })
+            .await;
+        });
+
+        (StatusCode::SWITCHING_PROTOCOLS, headers).into_response()
+    }
+}
+
+struct Deflater {
+    compress: Compress,
+    threshold: usize,
+    no_context_takeover: bool,
+}
+
+impl Deflater {
+    fn deflate(&mut self, data: &[u8]) -> Vec<u8> {
+        let start = self.compress.total_in();
+        let mut out = Vec::with_capacity(data.len() / 2 + 16);
+
+        // done once everything is read and the flush fit in the output
+        loop {
+            let read = (self.compress.total_in() - start) as usize;
+            if read == data.len() && out.len() < out.capacity() && out.ends_with(&[0, 0, 255, 255])
+            {
+                break;
+            }
+
+            out.reserve(out.capacity().max(64));
+            self.compress
+                .compress_vec(&data[read..], &mut out, FlushCompress::Sync)
+                .unwrap();
+        }
+
+        // a sync flush ends with an empty block, which receivers add back
+        out.truncate(out.len() - 4);
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+
+        out
+    }
+
+    /// A message of `payload`, compressed unless it is below the threshold.
+    fn message(&mut self, opcode: Data, payload: Bytes) -> ts::Message {
+        if payload.len() < self.threshold {
+            // text frames are only ever built from valid text
+            return match opcode {
+                Data::Text => ts::Message::Text(payload.try_into().unwrap()),
+                _ => ts::Message::Binary(payload),
+            };
+        }
+
+        let mut frame = Frame::message(self.deflate(&payload), OpCode::Data(opcode), true);
+        frame.header_mut().rsv1 = true;
+        ts::Message::Frame(frame)
+    }
+}
+
+struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+    /// The opcode and payload so far of a compressed message sent in
+    /// several frames.
+    message: Option<(OpCode, Vec<u8>)>,
+}
+
+impl Inflater {
+    fn inflate(&mut self, mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+        data.extend([0, 0, 255, 255]);
+
+        // the output never grows past a byte more than the largest message,
+        // which is enough to tell that it is too large
+        let start = self.decompress.total_in();
+        let mut out = Vec::with_capacity((data.len() * 4).min(MAX_MESSAGE_SIZE + 1));
+        loop {
+            let read = (self.decompress.total_in() - start) as usize;
+            if read == data.len() && out.len() < out.capacity() {
+                break;
+            }
+
+            out.reserve_exact(out.capacity().min(MAX_MESSAGE_SIZE + 1 - out.len()));
+            let before = out.len();
+            let status = self
+                .decompress
+                .decompress_vec(&data[read..], &mut out, FlushDecompress::Sync)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if out.len() > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "inflated message too large",
+                ));
+            }
+
+            match status {
+                // the client ended the stream, the next message starts anew
+                Status::StreamEnd => {
+                    self.decompress.reset(false);
+                    break;
+                }
+                Status::BufError if out.len() == before => {
+                    if (self.decompress.total_in() - start) as usize == data.len() {
+                        break;
+                    }
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "truncated compressed message",
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+
+        Ok(out)
+    }
+}
+
+/// Inflates the compressed messages of a client before tungstenite reads
+/// them, as it fails connections that send frames with RSV1 set.
+struct Inflate<S> {
+    stream: S,
+    inflater: Inflater,
+    /// Bytes read that do not make up a whole frame yet.
+    read: Vec<u8>,
+    /// Frames to pass on, starting at `written`.
+    out: Vec<u8>,
+    written: usize,
+    eof: bool,
+}
+
+impl<S> Inflate<S> {
+    fn new(stream: S, inflater: Inflater) -> Self {
+        Self {
+            stream,
+            inflater,
+            read: Vec::new(),
+            out: Vec::new(),
+            written: 0,
+            eof: false,
+        }
+    }
+
+    /// Moves the next whole frame from `read` to `out`, returning whether
+    /// there was one.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+
+        let mut cursor = Cursor::new(&self.read);
+        let Some((header, length)) = FrameHeader::parse(&mut cursor).map_err(invalid)? else {
+            return Ok(false);
+        };
+        if length > MAX_FRAME_SIZE as u64 {
+            return Err(invalid(ts::Error::Capacity(
+                ts::error::CapacityError::MessageTooLong {
+                    size: length as usize,
+                    max_size: MAX_FRAME_SIZE,
+                },
+            )));
+        }
+        let start = cursor.position() as usize;
+        let end = start + length as usize;
+        if self.read.len() < end {
+            return Ok(false);
+        }
+
+        let inflater = &mut self.inflater;
+        let compressed = match header.opcode {
+            OpCode::Data(Data::Text | Data::Binary) if header.rsv1 => {
+                inflater.message = Some((header.opcode, Vec::new()));
+                true
+            }
+            OpCode::Data(Data::Continue) => inflater.message.is_some(),
+            _ => false,
+        };
+        if !compressed {
+            self.out.extend(self.read.drain(..end));
+            return Ok(true);
+        }
+
+        let (opcode, message) = inflater.message.as_mut().unwrap();
+        let mask = header.mask.unwrap_or_default();
+        message.extend(
+            self.read[start..end]
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err(invalid(ts::Error::Capacity(
+                ts::error::CapacityError::MessageTooLong {
+                    size: message.len(),
+                    max_size: MAX_MESSAGE_SIZE,
+                },
+            )));
+        }
+        self.read.drain(..end);
+
+        if header.is_final {
+            let opcode = *opcode;
+            let (_, message) = inflater.message.take().unwrap();
+
+            // a zero mask, as tungstenite only reads masked frames
+            Frame::from_payload(
+                FrameHeader {
+                    opcode,
+                    mask: Some([0; 4]),
+                    ..FrameHeader::default()
+                },
+                inflater.inflate(message)?.into(),
+            )
+            .format(&mut self.out)
+            .map_err(invalid)?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Inflate<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.written < this.out.len() {
+                let count = buf.remaining().min(this.out.len() - this.written);
+                buf.put_slice(&this.out[this.written..this.written + count]);
+                this.written += count;
+                if this.written == this.out.len() {
+                    this.out.clear();
+                    this.written = 0;
+                }
+
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.next_frame()? {
+                continue;
+            }
+
+            // pass on the rest of a cut off frame for tungstenite to report
+            if this.eof {
+                if this.read.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                this.out = std::mem::take(&mut this.read);
+                continue;
+            }
+
+            let mut chunk = [0; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.stream).poll_read(cx, &mut chunk))?;
+            match chunk.filled() {
+                [] => this.eof = true,
+                filled => this.read.extend_from_slice(filled),
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Inflate<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/// A websocket sending and receiving axum's messages, compressing those
+/// sent if permessage-deflate was negotiated.
+pub struct WebSocket {
+    inner: Inner,
+}
+
+enum Inner {
+    Plain(ws::WebSocket),
+    Deflate {
+        stream: WebSocketStream<Inflate<TokioIo<Upgraded>>>,
+        deflater: Deflater,
+    },
+}
+
+impl Stream for WebSocket {
+    type Item = Result<Message, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let stream = match &mut self.inner {
+            Inner::Plain(socket) => return Pin::new(socket).poll_next(cx),
+            Inner::Deflate { stream, .. } => stream,
+        };
+
+        loop {
+            let message = match ready!(Pin::new(&mut *stream).poll_next(cx)) {
+                Some(Ok(message)) => message,
+                Some(Err(err)) => return Poll::Ready(Some(Err(axum::Error::new(err)))),
+                None => return Poll::Ready(None),
+            };
+
+            return Poll::Ready(Some(Ok(match message {
+                ts::Message::Text(text) => Message::Text(text.as_str().into()),
+                ts::Message::Binary(data) => Message::Binary(data),
+                ts::Message::Ping(data) => Message::Ping(data),
+                ts::Message::Pong(data) => Message::Pong(data),
+                ts::Message::Close(frame) => Message::Close(frame.map(|frame| CloseFrame {
+                    code: frame.code.into(),
+                    reason: frame.reason.as_str().into(),
+                })),
+                // only ever sent
+                ts::Message::Frame(_) => continue,
+            })));
+        }
+    }
+}
+
+impl Sink<Message> for WebSocket {
+    type Error = axum::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &mut self.inner {
+            Inner::Plain(socket) => Pin::new(socket).poll_ready(cx),
+            Inner::Deflate { stream, .. } => {
+                Pin::new(stream).poll_ready(cx).map_err(axum::Error::new)
+            }
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        let (stream, deflater) = match &mut self.inner {
+            Inner::Plain(socket) => return Pin::new(socket).start_send(item),
+            Inner::Deflate { stream, deflater } => (stream, deflater),
+        };
+
+        let message = match item {
+            Message::Text(text) => deflater.message(Data::Text, text.into()),
+            Message::Binary(data) => deflater.message(Data::Binary, data),
+            Message::Ping(data) => ts::Message::Ping(data),
+            Message::Pong(data) => ts::Message::Pong(data),
+            Message::Close(frame) => {
+                ts::Message::Close(frame.map(|frame| ts::protocol::CloseFrame {
+                    code: frame.code.into(),
+                    reason: frame.reason.as_str().into(),
+                }))
+            }
+        };
+
+        Pin::new(stream)
+            .start_send(message)
+            .map_err(axum::Error::new)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &mut self.inner {
+            Inner::Plain(socket) => Pin::new(socket).poll_flush(cx),
+            Inner::Deflate { stream, .. } => {
+                Pin::new(stream).poll_flush(cx).map_err(axum::Error::new)
+            }
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &mut self.inner {
+            Inner::Plain(socket) => Pin::new(socket).poll_close(cx),
+            Inner::Deflate { stream, .. } => {
+                Pin::new(stream).poll_close(cx).map_err(axum::Error::new)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    const COMPRESSION: Compression = Compression {
+        level: 6,
+        threshold: DEFAULT_THRESHOLD,
+    };
+
+    fn deflate(server_no_context_takeover: bool, client_no_context_takeover: bool) -> Deflate {
+        Deflate {
+            compression: COMPRESSION,
+            server_no_context_takeover,
+            client_no_context_takeover,
+        }
+    }
+
+    /// `payload` as masked client frames, split into `parts`, compressed if
+    /// `deflater` is set.
+    fn client_frames(
+        deflater: Option<&mut Deflater>,
+        opcode: Data,
+        payload: &[u8],
+        parts: usize,
+    ) -> Vec<u8> {
+        let compressed = deflater.is_some();
+        let payload = match deflater {
+            Some(deflater) => deflater.deflate(payload),
+            None => payload.to_vec(),
+        };
+
+        let mut out = Vec::new();
+        let chunks = payload
+            .chunks(payload.len().div_ceil(parts))
+            .collect::<Vec<_>>();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let header = FrameHeader {
+                is_final: i == chunks.len() - 1,
+                rsv1: compressed && i == 0,
+                opcode: match i {
+                    0 => OpCode::Data(opcode),
+                    _ => OpCode::Data(Data::Continue),
+                },
+                mask: Some([0x12, 0x34, 0x56, 0x78]),
+                ..FrameHeader::default()
+            };
+            Frame::from_payload(header, Bytes::copy_from_slice(chunk))
+                .format(&mut out)
+                .unwrap();
+        }
+
+        out
+    }
+
+    /// The messages a server that negotiated `deflate` reads from `frames`.
+    async fn receive(deflate: Deflate, frames: Vec<u8>) -> Vec<ts::Message> {
+        let (mut client, server) = tokio::io::duplex(1 << 20);
+        client.write_all(&frames).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let stream = Inflate::new(server, deflate.inflater());
+        WebSocketStream::from_raw_socket(stream, Role::Server, None)
+            .await
+            .take_while(|message| std::future::ready(message.is_ok()))
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    /// The frames a listener without the `json` or `compact` features was
+    /// sent while four scripted clients drew strokes, shapes, fills and text
+    /// for a minute, each a big-endian 32 bit length and the payload.
+    const CAPTURE: &[u8] = include_bytes!("../tests/fixtures/listener.frames");
+
+    fn captured_frames() -> Vec<&'static [u8]> {
+        let mut frames = Vec::new();
+        let mut rest = CAPTURE;
+        while let Some((len, after)) = rest.split_first_chunk::<4>() {
+            let (frame, after) = after.split_at(u32::from_be_bytes(*len) as usize);
+            frames.push(frame);
+            rest = after;
+        }
+
+        frames
+    }
+
+    /// How many bytes the captured frames take on the wire, headers
+    /// included, compressed as agreed in `deflate`.
+    fn replay(deflate: Option<Deflate>) -> usize {
+        let mut deflater = deflate.map(|deflate| deflate.deflater());
+        let mut inflater = deflate.map(|deflate| deflate.inflater());
+
+        let mut wire = 0;
+        for payload in captured_frames() {
+            let payload = Bytes::from_static(payload);
+            let message = match &mut deflater {
+                Some(deflater) => deflater.message(Data::Binary, payload.clone()),
+                None => ts::Message::Binary(payload.clone()),
+            };
+
+            let frame = match message {
+                ts::Message::Frame(frame) => {
+                    let inflated = inflater
+                        .as_mut()
+                        .unwrap()
+                        .inflate(frame.payload().to_vec())
+                        .unwrap();
+                    assert_eq!(inflated, payload);
+
+                    frame
+                }
+                ts::Message::Binary(payload) => {
+                    Frame::message(payload, OpCode::Data(Data::Binary), true)
+                }
+                _ => unreachable!(),
+            };
+            wire += frame.len();
+        }
+
+        wire
+    }
+
+    #[test]
+    fn compresses_a_recorded_session() {
+        let plain = replay(None);
+        let takeover = replay(Some(deflate(false, false)));
+        let no_takeover = replay(Some(deflate(true, false)));
+
+        // 73% and 94% of the bytes when recorded
+        let report = format!("{plain} bytes, {takeover} with takeover, {no_takeover} without");
+        assert!(takeover * 4 < plain * 3, "{report}");
+        assert!(no_takeover < plain, "{report}");
+    }
+
+    #[test]
+    fn negotiates_parameters() {
+        assert_eq!(
+            negotiate("permessage-deflate", COMPRESSION),
+            Some(deflate(false, false))
+        );
+        assert_eq!(
+            negotiate(
+                "permessage-deflate; server_no_context_takeover; client_max_window_bits",
+                COMPRESSION
+            ),
+            Some(deflate(true, false))
+        );
+        assert_eq!(
+            negotiate(
+                r#"permessage-deflate;client_no_context_takeover; client_max_window_bits=10; server_max_window_bits="15""#,
+                COMPRESSION
+            ),
+            Some(deflate(false, true))
+        );
+
+        assert_eq!(deflate(false, false).response(), "permessage-deflate");
+        assert_eq!(
+            deflate(true, true).response(),
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        );
+    }
+
+    #[test]
+    fn refuses_bad_offers() {
+        for offers in [
+            "",
+            "x-webkit-deflate-frame",
+            "permessage-deflate; server_max_window_bits=10",
+            "permessage-deflate; server_max_window_bits",
+            "permessage-deflate; server_no_context_takeover=1",
+            "permessage-deflate; bogus",
+        ] {
+            assert_eq!(negotiate(offers, COMPRESSION), None, "{offers}");
+        }
+
+        // the first acceptable offer is taken
+        assert_eq!(
+            negotiate(
+                "permessage-deflate; server_max_window_bits=10, permessage-deflate; client_no_context_takeover",
+                COMPRESSION
+            ),
+            Some(deflate(false, true))
+        );
+    }
+
+    #[test]
+    fn sends_small_messages_as_they_are() {
+        let mut deflater = deflate(false, false).deflater();
+
+        let small = Bytes::from_static(&[7; DEFAULT_THRESHOLD - 1]);
+        assert_eq!(
+            deflater.message(Data::Binary, small.clone()),
+            ts::Message::Binary(small)
+        );
+        assert_eq!(
+            deflater.message(Data::Text, Bytes::from_static(b"{}")),
+            ts::Message::text("{}")
+        );
+
+        let ts::Message::Frame(frame) =
+            deflater.message(Data::Binary, Bytes::from_static(&[7; DEFAULT_THRESHOLD]))
+        else {
+            panic!("not compressed");
+        };
+        assert!(frame.header().rsv1);
+    }
+
+    #[tokio::test]
+    async fn inflates_fragmented_messages() {
+        let deflate = deflate(false, false);
+        let mut deflater = deflate.deflater();
+        let text = r#"{"type":"draw","messages":[]}"#.repeat(20);
+        let binary = (0..2000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+
+        let mut frames = client_frames(Some(&mut deflater), Data::Text, text.as_bytes(), 1);
+        frames.extend(client_frames(Some(&mut deflater), Data::Binary, &binary, 3));
+        // uncompressed messages pass through, also between compressed ones
+        frames.extend(client_frames(None, Data::Binary, &binary, 2));
+        frames.extend(client_frames(Some(&mut deflater), Data::Binary, &binary, 5));
+
+        assert_eq!(
+            receive(deflate, frames).await,
+            [
+                ts::Message::text(text),
+                ts::Message::binary(binary.clone()),
+                ts::Message::binary(binary.clone()),
+                ts::Message::binary(binary),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_messages_that_inflate_too_large() {
+        let deflate = deflate(false, false);
+
+        // a megabyte of zeros flushed on its own inflates the same after
+        // any other zeros, so repeating it makes a message of any size
+        let mut zeros = deflate.deflater().deflate(&vec![0; 1 << 20]);
+        zeros.extend([0, 0, 255, 255]);
+        let bomb = |megabytes: usize| {
+            let mut bomb = zeros.repeat(megabytes);
+            bomb.truncate(bomb.len() - 4);
+            bomb
+        };
+
+        let largest = MAX_MESSAGE_SIZE >> 20;
+        let inflated = deflate.inflater().inflate(bomb(largest)).unwrap();
+        assert_eq!(inflated.len(), MAX_MESSAGE_SIZE);
+        assert!(inflated.capacity() <= MAX_MESSAGE_SIZE + 1);
+        drop(inflated);
+
+        let err = deflate.inflater().inflate(bomb(largest + 1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // the connection ends with the message, far past the largest
+        let mut frames = client_frames(None, Data::Text, b"before", 1);
+        let header = FrameHeader {
+            rsv1: true,
+            opcode: OpCode::Data(Data::Binary),
+            mask: Some([0; 4]),
+            ..FrameHeader::default()
+        };
+        Frame::from_payload(header, bomb(4 * largest).into())
+            .format(&mut frames)
+            .unwrap();
+        frames.extend(client_frames(None, Data::Text, b"after", 1));
+        assert_eq!(
+            receive(deflate, frames).await,
+            [ts::Message::text("before")]
+        );
+    }
+
+    #[tokio::test]
+    async fn resets_the_context_without_takeover() {
+        let payload = b"draw together, draw together, draw together".repeat(4);
+
+        // every message is compressed on its own
+        let mut deflater = deflate(true, false).deflater();
+        let first = deflater.deflate(&payload);
+        assert_eq!(deflater.deflate(&payload), first);
+
+        // otherwise later ones refer back to earlier ones
+        let mut deflater = deflate(false, false).deflater();
+        let first = deflater.deflate(&payload);
+        let second = deflater.deflate(&payload);
+        assert!(second.len() < first.len());
+
+        let mut inflater = deflate(false, false).inflater();
+        assert_eq!(inflater.inflate(first.clone()).unwrap(), payload);
+        assert_eq!(inflater.inflate(second.clone()).unwrap(), payload);
+
+        // an inflater without takeover forgets the messages before
+        let mut inflater = deflate(false, true).inflater();
+        assert_eq!(inflater.inflate(first).unwrap(), payload);
+        assert!(
+            inflater
+                .inflate(second)
+                .ok()
+                .is_none_or(|inflated| inflated != payload)
+        );
+
+        // so a client without takeover sends messages that each inflate alone
+        let deflate = deflate(true, true);
+        let mut deflater = deflate.deflater();
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            frames.extend(client_frames(
+                Some(&mut deflater),
+                Data::Binary,
+                &payload,
+                2,
+            ));
+        }
+
+        assert_eq!(
+            receive(deflate, frames).await,
+            vec![ts::Message::binary(payload); 3]
+        );
+    }
+}